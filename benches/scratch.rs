@@ -0,0 +1,46 @@
+//! Compares many small signatures allocating their output buffers with ones reusing a buffer.
+//!
+//! Run with `cargo bench --features nightly`.
+
+#![cfg(feature = "nightly")]
+#![cfg_attr(feature = "nightly", feature(test))]
+
+extern crate test;
+
+use librsync::{Signature, SignatureBuilder};
+use std::io;
+use test::Bencher;
+
+const INPUT_LEN: usize = 1024;
+const SIGNATURES: usize = 100;
+
+fn builder() -> SignatureBuilder {
+    SignatureBuilder::new().block_len(256).strong_len(8)
+}
+
+#[bench]
+fn signatures_allocating(b: &mut Bencher) {
+    let data: Vec<u8> = (0..INPUT_LEN).map(|i| (i % 251) as u8).collect();
+    b.bytes = (INPUT_LEN * SIGNATURES) as u64;
+    b.iter(|| {
+        for _ in 0..SIGNATURES {
+            let mut sig = builder().build(&data[..]).unwrap();
+            io::copy(&mut sig, &mut io::sink()).unwrap();
+        }
+    });
+}
+
+#[bench]
+fn signatures_reusing(b: &mut Bencher) {
+    let data: Vec<u8> = (0..INPUT_LEN).map(|i| (i % 251) as u8).collect();
+    let mut scratch = Some(Vec::new());
+    b.bytes = (INPUT_LEN * SIGNATURES) as u64;
+    b.iter(|| {
+        for _ in 0..SIGNATURES {
+            let buf = scratch.take().unwrap();
+            let mut sig = Signature::new_in(&data[..], builder(), buf).unwrap();
+            io::copy(&mut sig, &mut io::sink()).unwrap();
+            scratch = Some(sig.into_parts().1);
+        }
+    });
+}
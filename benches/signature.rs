@@ -0,0 +1,45 @@
+//! Compares the signature of a file read through a buffer with the one of a memory-mapped file.
+//!
+//! Run with `cargo bench --features "nightly mmap"`.
+
+#![cfg(all(feature = "nightly", feature = "mmap"))]
+#![cfg_attr(feature = "nightly", feature(test))]
+
+extern crate test;
+
+use librsync::{whole, SignatureType};
+use std::fs::{self, File};
+use std::io::{self, BufReader};
+use std::path::PathBuf;
+use test::Bencher;
+
+const FILE_LEN: usize = 16 * 1024 * 1024;
+
+fn data_file(name: &str) -> PathBuf {
+    let path = std::env::temp_dir().join(format!("librsync-bench-{}", name));
+    let data: Vec<u8> = (0..FILE_LEN).map(|i| (i % 251) as u8).collect();
+    fs::write(&path, data).unwrap();
+    path
+}
+
+#[bench]
+fn signature_buffered(b: &mut Bencher) {
+    let path = data_file("buffered");
+    b.bytes = FILE_LEN as u64;
+    b.iter(|| {
+        let mut file = BufReader::new(File::open(&path).unwrap());
+        whole::signature_with_options(&mut file, &mut io::sink(), 2048, 32, SignatureType::Blake2)
+            .unwrap()
+    });
+    fs::remove_file(&path).unwrap();
+}
+
+#[bench]
+fn signature_mmap(b: &mut Bencher) {
+    let path = data_file("mmap");
+    b.bytes = FILE_LEN as u64;
+    b.iter(|| {
+        whole::signature_mmap(&path, &mut io::sink(), 2048, 32, SignatureType::Blake2).unwrap()
+    });
+    fs::remove_file(&path).unwrap();
+}
@@ -59,6 +59,7 @@ fn main() {
         .file("librsync/src/version.c")
         .file("librsync/src/whole.c")
         .file("librsync/src/blake2/blake2b-ref.c")
+        .file("hash_shim.c")
         .compile("librsync.a");
     println!("cc finished!!");
 }
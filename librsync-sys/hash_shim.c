@@ -0,0 +1,56 @@
+/*
+ * Opaque access to the hash functions bundled with librsync.
+ *
+ * The layouts of rs_mdfour_t and blake2b_state are private to librsync: the former depends on
+ * its configuration, and the latter differs between versions of the BLAKE2 reference code. The
+ * states are therefore allocated here, where their real size is known, and handled from Rust
+ * only through pointers.
+ */
+
+#include "config.h"
+
+#include <stdlib.h>
+
+#include "librsync.h"
+#include "mdfour.h"
+#include "blake2.h"
+
+rs_mdfour_t *librsync_sys_mdfour_new(void)
+{
+    rs_mdfour_t *md = malloc(sizeof(rs_mdfour_t));
+
+    if (md != NULL)
+        rs_mdfour_begin(md);
+    return md;
+}
+
+void librsync_sys_mdfour_free(rs_mdfour_t *md)
+{
+    free(md);
+}
+
+blake2b_state *librsync_sys_blake2b_new(size_t outlen)
+{
+    blake2b_state *S = malloc(sizeof(blake2b_state));
+
+    if (S != NULL && blake2b_init(S, outlen) != 0) {
+        free(S);
+        return NULL;
+    }
+    return S;
+}
+
+int librsync_sys_blake2b_update(blake2b_state *S, const void *in, size_t inlen)
+{
+    return blake2b_update(S, in, inlen);
+}
+
+int librsync_sys_blake2b_final(blake2b_state *S, void *out, size_t outlen)
+{
+    return blake2b_final(S, out, outlen);
+}
+
+void librsync_sys_blake2b_free(blake2b_state *S)
+{
+    free(S);
+}
@@ -7,6 +7,8 @@ pub type rs_magic_number = c_int;
 pub const RS_DELTA_MAGIC: c_int = 0x7273_0236;
 pub const RS_MD4_SIG_MAGIC: c_int = 0x7273_0136;
 pub const RS_BLAKE2_SIG_MAGIC: c_int = 0x7273_0137;
+pub const RS_RK_MD4_SIG_MAGIC: c_int = 0x7273_0146;
+pub const RS_RK_BLAKE2_SIG_MAGIC: c_int = 0x7273_0147;
 
 pub type rs_result = c_int;
 pub const RS_DONE: c_int = 0;
@@ -39,6 +41,9 @@ pub type rs_long_t = c_longlong;
 
 pub enum rs_job_t {}
 pub enum rs_signature_t {}
+// the hash states, allocated by the shim in hash_shim.c
+pub enum rs_mdfour_t {}
+pub enum blake2b_state {}
 
 #[repr(C)]
 pub struct rs_buffers_t {
@@ -49,6 +54,26 @@ pub struct rs_buffers_t {
     pub avail_out: size_t,
 }
 
+#[repr(C)]
+pub struct rs_stats_t {
+    pub op: *const c_char,
+    pub lit_cmds: c_int,
+    pub lit_bytes: rs_long_t,
+    pub lit_cmdbytes: rs_long_t,
+    pub copy_cmds: rs_long_t,
+    pub copy_bytes: rs_long_t,
+    pub copy_cmdbytes: rs_long_t,
+    pub sig_cmds: rs_long_t,
+    pub sig_bytes: rs_long_t,
+    pub false_matches: c_int,
+    pub sig_blocks: rs_long_t,
+    pub block_len: size_t,
+    pub in_bytes: rs_long_t,
+    pub out_bytes: rs_long_t,
+    pub start: time_t,
+    pub end: time_t,
+}
+
 pub type rs_copy_cb = extern "C" fn(
     opaque: *mut c_void,
     pos: rs_long_t,
@@ -60,6 +85,7 @@ pub type rs_trace_fn_t = extern "C" fn(level: rs_loglevel, msg: *const c_char);
 extern "C" {
     pub fn rs_job_iter(job: *mut rs_job_t, buffers: *mut rs_buffers_t) -> rs_result;
     pub fn rs_job_free(job: *mut rs_job_t) -> rs_result;
+    pub fn rs_job_statistics(job: *mut rs_job_t) -> *const rs_stats_t;
 
     pub fn rs_sig_begin(
         new_block_len: size_t,
@@ -72,6 +98,24 @@ extern "C" {
     pub fn rs_free_sumset(sums: *mut rs_signature_t);
     pub fn rs_patch_begin(copy_cb: rs_copy_cb, copy_arg: *mut c_void) -> *mut rs_job_t;
 
+    pub fn librsync_sys_mdfour_new() -> *mut rs_mdfour_t;
+    pub fn librsync_sys_mdfour_free(md: *mut rs_mdfour_t);
+    pub fn rs_mdfour_update(md: *mut rs_mdfour_t, in_void: *const c_void, n: size_t);
+    pub fn rs_mdfour_result(md: *mut rs_mdfour_t, out: *mut c_uchar);
+
+    pub fn librsync_sys_blake2b_new(outlen: size_t) -> *mut blake2b_state;
+    pub fn librsync_sys_blake2b_update(
+        S: *mut blake2b_state,
+        input: *const c_void,
+        inlen: size_t,
+    ) -> c_int;
+    pub fn librsync_sys_blake2b_final(
+        S: *mut blake2b_state,
+        out: *mut c_void,
+        outlen: size_t,
+    ) -> c_int;
+    pub fn librsync_sys_blake2b_free(S: *mut blake2b_state);
+
     pub fn rs_trace_set_level(level: rs_loglevel);
     pub fn rs_trace_to(f: rs_trace_fn_t);
 }
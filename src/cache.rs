@@ -0,0 +1,135 @@
+use std::cmp;
+use std::collections::VecDeque;
+use std::io::{self, Read, Seek, SeekFrom};
+
+/// A basis file which keeps the recently read regions in memory.
+///
+/// A delta may copy the same region of the basis file several times, for example when a block
+/// is repeated in the new file. When reading the basis file is expensive, like with a remote
+/// file, wrapping it in a `CachingBase` serves the repeated reads from memory. The regions are
+/// evicted in least recently used order, so that at most `cache_size` bytes are kept.
+///
+/// A read is served from the cache if a cached region contains its starting offset, even if
+/// the region ends before the requested length, so that the read may be shorter than the one
+/// given to the inner stream. Such short reads are handled by `Patch`.
+pub struct CachingBase<B> {
+    inner: B,
+    // the cached regions with their offsets, the most recently used first
+    regions: VecDeque<(u64, Vec<u8>)>,
+    cached: usize,
+    cache_size: usize,
+    pos: u64,
+    // the position of the inner stream, if known
+    inner_pos: Option<u64>,
+    hits: u64,
+    misses: u64,
+}
+
+impl<B: Read + Seek> CachingBase<B> {
+    /// Creates a new caching base, keeping at most `cache_size` bytes of `inner` in memory.
+    pub fn new(inner: B, cache_size: usize) -> Self {
+        CachingBase {
+            inner,
+            regions: VecDeque::new(),
+            cached: 0,
+            cache_size,
+            pos: 0,
+            inner_pos: None,
+            hits: 0,
+            misses: 0,
+        }
+    }
+
+    /// Returns the number of reads served from the cache.
+    pub fn hits(&self) -> u64 {
+        self.hits
+    }
+
+    /// Returns the number of reads forwarded to the inner stream.
+    pub fn misses(&self) -> u64 {
+        self.misses
+    }
+
+    /// Unwraps this base, returning the inner stream.
+    pub fn into_inner(self) -> B {
+        self.inner
+    }
+
+    fn read_inner(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        if self.inner_pos != Some(self.pos) {
+            self.inner_pos = None;
+            self.inner.seek(SeekFrom::Start(self.pos))?;
+        }
+        let read = self.inner.read(buf)?;
+        self.inner_pos = Some(self.pos + read as u64);
+        Ok(read)
+    }
+
+    fn insert(&mut self, offset: u64, data: &[u8]) {
+        if data.is_empty() || data.len() > self.cache_size {
+            return;
+        }
+        while self.cached + data.len() > self.cache_size {
+            match self.regions.pop_back() {
+                Some((_, evicted)) => self.cached -= evicted.len(),
+                None => break,
+            }
+        }
+        self.cached += data.len();
+        self.regions.push_front((offset, data.to_vec()));
+    }
+}
+
+impl<B: Read + Seek> Read for CachingBase<B> {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        if buf.is_empty() {
+            return Ok(0);
+        }
+        let pos = self.pos;
+        let hit = self
+            .regions
+            .iter()
+            .position(|(offset, data)| *offset <= pos && pos - offset < data.len() as u64);
+        if let Some(index) = hit {
+            let region = self.regions.remove(index).unwrap();
+            let start = (pos - region.0) as usize;
+            let len = cmp::min(buf.len(), region.1.len() - start);
+            buf[..len].copy_from_slice(&region.1[start..start + len]);
+            self.regions.push_front(region);
+            self.hits += 1;
+            self.pos += len as u64;
+            return Ok(len);
+        }
+        self.misses += 1;
+        let read = self.read_inner(buf)?;
+        self.insert(pos, &buf[..read]);
+        self.pos += read as u64;
+        Ok(read)
+    }
+}
+
+impl<B: Read + Seek> Seek for CachingBase<B> {
+    fn seek(&mut self, pos: SeekFrom) -> io::Result<u64> {
+        let target = match pos {
+            SeekFrom::Start(offset) => Some(offset),
+            SeekFrom::Current(offset) if offset >= 0 => self.pos.checked_add(offset as u64),
+            SeekFrom::Current(offset) => self.pos.checked_sub(offset.unsigned_abs()),
+            SeekFrom::End(_) => {
+                self.inner_pos = None;
+                let target = self.inner.seek(pos)?;
+                self.inner_pos = Some(target);
+                Some(target)
+            }
+        };
+        match target {
+            Some(target) => {
+                self.pos = target;
+                Ok(target)
+            }
+            None => Err(io::Error::new(
+                io::ErrorKind::InvalidInput,
+                "invalid seek to a negative or overflowing position",
+            )),
+        }
+    }
+}
@@ -0,0 +1,153 @@
+use std::cmp;
+use std::io::{self, BufRead, Read, Seek, SeekFrom};
+
+/// An adapter reading from an iterator of byte chunks.
+///
+/// This allows to feed `Signature`, `Delta` and `Patch` with data coming from sources not
+/// implementing `Read`, for example a channel, or the frames of a network protocol. Empty chunks
+/// are skipped, and the stream ends when the iterator does. An error returned by the iterator is
+/// reported by the read operation that reaches it.
+///
+/// Since the chunks are already stored in memory, `ChunkReader` also implements `BufRead`, and
+/// can be used with the `with_buf_read` constructors to avoid an additional copy.
+pub struct ChunkReader<I> {
+    chunks: I,
+    chunk: Vec<u8>,
+    pos: usize,
+}
+
+/// A basis file assembled from fixed-size chunks, fetched by index.
+///
+/// This type is used by `Patch::from_chunk_fn`, for bases stored as chunks, like in deduplicated
+/// backups. The offsets requested by the copy commands are translated into the index of a chunk
+/// and an offset within it, and the chunk is fetched with the given function. The last fetched
+/// chunk is kept, so that consecutive copies from the same chunk fetch it only once.
+///
+/// Every chunk must be `chunk_size` bytes long, but the last one, which may be shorter. A chunk
+/// shorter than that, or empty, marks the end of the basis file. Seeking relative to the end is
+/// not supported, since the length of the basis is not known.
+pub struct ChunkedBase<F> {
+    fetch: F,
+    chunk_size: u64,
+    // the index and the data of the last fetched chunk
+    chunk: Option<(u64, Vec<u8>)>,
+    pos: u64,
+}
+
+impl<I: Iterator<Item = io::Result<Vec<u8>>>> ChunkReader<I> {
+    /// Creates a new reader from an iterator of byte chunks.
+    pub fn new(chunks: I) -> Self {
+        ChunkReader {
+            chunks,
+            chunk: Vec::new(),
+            pos: 0,
+        }
+    }
+
+    /// Unwraps this reader, returning the underlying iterator.
+    ///
+    /// The unread bytes of the current chunk are lost.
+    pub fn into_inner(self) -> I {
+        self.chunks
+    }
+}
+
+impl<F: FnMut(u64) -> io::Result<Vec<u8>>> ChunkedBase<F> {
+    /// Creates a new basis file, fetching its chunks of `chunk_size` bytes with `fetch`.
+    pub fn new(fetch: F, chunk_size: u64) -> Self {
+        ChunkedBase {
+            fetch,
+            chunk_size,
+            chunk: None,
+            pos: 0,
+        }
+    }
+
+    /// Unwraps this basis, returning the function fetching the chunks.
+    pub fn into_inner(self) -> F {
+        self.fetch
+    }
+}
+
+impl<F: FnMut(u64) -> io::Result<Vec<u8>>> Read for ChunkedBase<F> {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        if self.chunk_size == 0 {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidInput,
+                "zero chunk size",
+            ));
+        }
+        let index = self.pos / self.chunk_size;
+        let offset = (self.pos % self.chunk_size) as usize;
+        let cached = matches!(self.chunk, Some((i, _)) if i == index);
+        if !cached {
+            self.chunk = Some((index, (self.fetch)(index)?));
+        }
+        let chunk = match self.chunk {
+            Some((_, ref chunk)) => chunk,
+            None => unreachable!(),
+        };
+        if offset >= chunk.len() {
+            return Ok(0);
+        }
+        let len = cmp::min(buf.len(), chunk.len() - offset);
+        buf[..len].copy_from_slice(&chunk[offset..offset + len]);
+        self.pos += len as u64;
+        Ok(len)
+    }
+}
+
+impl<F> Seek for ChunkedBase<F> {
+    fn seek(&mut self, pos: SeekFrom) -> io::Result<u64> {
+        let pos = match pos {
+            SeekFrom::Start(offset) => Some(offset),
+            SeekFrom::Current(offset) if offset >= 0 => self.pos.checked_add(offset as u64),
+            SeekFrom::Current(offset) => self.pos.checked_sub(offset.unsigned_abs()),
+            SeekFrom::End(_) => {
+                return Err(io::Error::new(
+                    io::ErrorKind::Unsupported,
+                    "the length of a chunked base is unknown",
+                ))
+            }
+        };
+        match pos {
+            Some(pos) => {
+                self.pos = pos;
+                Ok(pos)
+            }
+            None => Err(io::Error::new(
+                io::ErrorKind::InvalidInput,
+                "invalid seek to a negative or overflowing position",
+            )),
+        }
+    }
+}
+
+impl<I: Iterator<Item = io::Result<Vec<u8>>>> Read for ChunkReader<I> {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        let available = self.fill_buf()?;
+        let len = cmp::min(buf.len(), available.len());
+        buf[..len].copy_from_slice(&available[..len]);
+        self.consume(len);
+        Ok(len)
+    }
+}
+
+impl<I: Iterator<Item = io::Result<Vec<u8>>>> BufRead for ChunkReader<I> {
+    fn fill_buf(&mut self) -> io::Result<&[u8]> {
+        while self.pos == self.chunk.len() {
+            match self.chunks.next() {
+                Some(chunk) => {
+                    self.chunk = chunk?;
+                    self.pos = 0;
+                }
+                None => break,
+            }
+        }
+        Ok(&self.chunk[self.pos..])
+    }
+
+    fn consume(&mut self, amt: usize) {
+        self.pos = cmp::min(self.pos + amt, self.chunk.len());
+    }
+}
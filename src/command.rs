@@ -0,0 +1,440 @@
+use std::cmp;
+use std::io::{self, Read, Write};
+
+use crate::{Error, Result};
+
+/// The magic number at the start of every delta.
+pub const DELTA_MAGIC: [u8; 4] = [0x72, 0x73, 0x02, 0x36];
+
+const OP_END: u8 = 0x00;
+const OP_LITERAL_64: u8 = 0x40;
+const OP_LITERAL_N1: u8 = 0x41;
+const OP_COPY_N1_N1: u8 = 0x45;
+const OP_COPY_N8_N8: u8 = 0x54;
+
+// The possible widths of integer parameters, in bytes.
+const INT_LENS: [usize; 4] = [1, 2, 4, 8];
+
+// The size of the chunks given to `for_each_literal`.
+const LITERAL_CHUNK_LEN: usize = 8 * 1024;
+
+/// A command of a delta.
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub enum Command {
+    /// Appends the given data to the output.
+    Literal(Vec<u8>),
+    /// Appends a region of the basis file to the output.
+    Copy {
+        /// The position of the region in the basis file.
+        offset: u64,
+        /// The length of the region, in bytes.
+        len: u64,
+    },
+}
+
+/// A parser for the commands of a delta.
+///
+/// This type reads a delta from a `Read` stream, and iterates over its commands, in order. The
+/// final end command is not returned, and the iteration stops at the first error, like a bad
+/// magic number or a corrupt command. Literal commands carry their data, which is loaded in
+/// memory: use `for_each_literal` to inspect it without buffering big literals.
+pub struct DeltaCommands<R> {
+    input: R,
+    started: bool,
+    ended: bool,
+}
+
+// The header of a command, without its literal data.
+enum Op {
+    End,
+    Literal(u64),
+    Copy { offset: u64, len: u64 },
+}
+
+impl<R: Read> DeltaCommands<R> {
+    /// Creates a new parser, reading the delta from the given stream.
+    pub fn new(input: R) -> Self {
+        DeltaCommands {
+            input,
+            started: false,
+            ended: false,
+        }
+    }
+
+    /// Calls the given function with the data of all the literal commands, in order.
+    ///
+    /// The data is read in chunks of limited size, so a big literal command can be given in
+    /// more calls. The copy commands are skipped. The parser is consumed, since the data is not
+    /// stored.
+    pub fn for_each_literal<F: FnMut(&[u8])>(mut self, mut f: F) -> Result<()> {
+        let mut buf = vec![0; LITERAL_CHUNK_LEN];
+        loop {
+            match self.read_op()? {
+                Op::End => return Ok(()),
+                Op::Literal(mut len) => {
+                    while len > 0 {
+                        let chunk_len = cmp::min(len, buf.len() as u64) as usize;
+                        self.input.read_exact(&mut buf[..chunk_len])?;
+                        f(&buf[..chunk_len]);
+                        len -= chunk_len as u64;
+                    }
+                }
+                Op::Copy { .. } => (),
+            }
+        }
+    }
+
+    fn read_command(&mut self) -> Result<Option<Command>> {
+        let cmd = match self.read_op()? {
+            Op::End => return Ok(None),
+            Op::Literal(len) => self.read_literal(len)?,
+            Op::Copy { offset, len } => Command::Copy { offset, len },
+        };
+        Ok(Some(cmd))
+    }
+
+    fn read_op(&mut self) -> Result<Op> {
+        if !self.started {
+            let mut magic = [0; 4];
+            self.input.read_exact(&mut magic)?;
+            if magic != DELTA_MAGIC {
+                return Err(Error::BadMagic);
+            }
+            self.started = true;
+        }
+        let op = self.read_int(1)? as u8;
+        let op = match op {
+            OP_END => Op::End,
+            1..=OP_LITERAL_64 => Op::Literal(u64::from(op)),
+            OP_LITERAL_N1..=0x44 => {
+                Op::Literal(self.read_int(INT_LENS[usize::from(op - OP_LITERAL_N1)])?)
+            }
+            OP_COPY_N1_N1..=OP_COPY_N8_N8 => {
+                let kind = usize::from(op - OP_COPY_N1_N1);
+                let offset = self.read_int(INT_LENS[kind / 4])?;
+                let len = self.read_int(INT_LENS[kind % 4])?;
+                Op::Copy { offset, len }
+            }
+            _ => return Err(Error::Corrupt),
+        };
+        Ok(op)
+    }
+
+    fn read_literal(&mut self, len: u64) -> Result<Command> {
+        let mut data = Vec::new();
+        let read = (&mut self.input).take(len).read_to_end(&mut data)?;
+        if (read as u64) < len {
+            return Err(io::Error::from(io::ErrorKind::UnexpectedEof).into());
+        }
+        Ok(Command::Literal(data))
+    }
+
+    // Reads a big endian integer of `len` bytes.
+    fn read_int(&mut self, len: usize) -> Result<u64> {
+        let mut buf = [0; 8];
+        self.input.read_exact(&mut buf[8 - len..])?;
+        let value = u64::from_be_bytes(buf);
+        // librsync uses signed integers
+        if value > i64::MAX as u64 {
+            return Err(Error::Corrupt);
+        }
+        Ok(value)
+    }
+}
+
+impl<R: Read> Iterator for DeltaCommands<R> {
+    type Item = Result<Command>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        if self.ended {
+            return None;
+        }
+        let res = self.read_command();
+        match res {
+            Ok(None) | Err(_) => self.ended = true,
+            Ok(Some(_)) => (),
+        }
+        res.transpose()
+    }
+}
+
+impl Command {
+    /// Writes the command in the delta format, by using its shortest encoding.
+    pub fn write_to<W: Write + ?Sized>(&self, out: &mut W) -> io::Result<()> {
+        match *self {
+            Command::Literal(ref data) => {
+                let len = data.len() as u64;
+                if len == 0 {
+                    return Ok(());
+                }
+                if len <= u64::from(OP_LITERAL_64) {
+                    out.write_all(&[len as u8])?;
+                } else {
+                    let kind = int_kind(len);
+                    out.write_all(&[OP_LITERAL_N1 + kind as u8])?;
+                    write_int(out, len, INT_LENS[kind])?;
+                }
+                out.write_all(data)
+            }
+            Command::Copy { offset, len } => {
+                let (offset_kind, len_kind) = (int_kind(offset), int_kind(len));
+                out.write_all(&[OP_COPY_N1_N1 + (offset_kind * 4 + len_kind) as u8])?;
+                write_int(out, offset, INT_LENS[offset_kind])?;
+                write_int(out, len, INT_LENS[len_kind])
+            }
+        }
+    }
+}
+
+pub fn write_end<W: Write + ?Sized>(out: &mut W) -> io::Result<()> {
+    out.write_all(&[OP_END])
+}
+
+/// Returns the format version in the magic number of a delta.
+///
+/// The magic number is made of `rs`, the format version and the delta type. `Error::BadMagic` is
+/// returned if the other bytes do not match, whatever the version.
+pub fn format_version(magic: [u8; 4]) -> Result<u8> {
+    if magic[..2] != DELTA_MAGIC[..2] || magic[3] != DELTA_MAGIC[3] {
+        return Err(Error::BadMagic);
+    }
+    Ok(magic[2])
+}
+
+// Returns the index in `INT_LENS` of the shortest width able to hold the value.
+fn int_kind(value: u64) -> usize {
+    INT_LENS
+        .iter()
+        .position(|&len| len == 8 || value >> (len * 8) == 0)
+        .unwrap()
+}
+
+fn write_int<W: Write + ?Sized>(out: &mut W, value: u64, len: usize) -> io::Result<()> {
+    out.write_all(&value.to_be_bytes()[8 - len..])
+}
+
+// Rewrites a delta, merging the copy commands referring to contiguous regions of the basis file.
+//
+// Returns the number of bytes written.
+pub fn coalesce<R: Read, W: Write + ?Sized>(delta: R, out: &mut W) -> Result<u64> {
+    let mut out = CountingWriter {
+        inner: out,
+        count: 0,
+    };
+    out.write_all(&DELTA_MAGIC)?;
+    let mut pending: Option<(u64, u64)> = None;
+    for cmd in DeltaCommands::new(delta) {
+        match cmd? {
+            Command::Copy { offset, len } => match pending {
+                Some((pending_offset, ref mut pending_len))
+                    if pending_offset + *pending_len == offset =>
+                {
+                    *pending_len += len;
+                }
+                _ => {
+                    flush_copy(&mut out, pending.take())?;
+                    pending = Some((offset, len));
+                }
+            },
+            literal => {
+                flush_copy(&mut out, pending.take())?;
+                literal.write_to(&mut out)?;
+            }
+        }
+    }
+    flush_copy(&mut out, pending)?;
+    write_end(&mut out)?;
+    Ok(out.count)
+}
+
+fn flush_copy<W: Write + ?Sized>(out: &mut W, copy: Option<(u64, u64)>) -> io::Result<()> {
+    match copy {
+        Some((offset, len)) => Command::Copy { offset, len }.write_to(out),
+        None => Ok(()),
+    }
+}
+
+// Writes to an output stream, counting the written bytes.
+pub struct CountingWriter<'a, W: ?Sized + 'a> {
+    pub inner: &'a mut W,
+    pub count: u64,
+}
+
+impl<'a, W: Write + ?Sized> Write for CountingWriter<'a, W> {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        let written = self.inner.write(buf)?;
+        self.count += written as u64;
+        Ok(written)
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        self.inner.flush()
+    }
+}
+
+// Follows the commands of a delta while it is produced, to find the boundaries of commands.
+//
+// The position of a boundary is given both as a delta length and as the length of the new file
+// described by the delta up to that point.
+#[derive(Debug)]
+pub struct CommandTracker {
+    header_left: usize,
+    state: TrackerState,
+    // the last complete command ends at these delta and new file lengths
+    boundary: (u64, u64),
+    // the number of bytes of delta followed so far
+    out_pos: u64,
+}
+
+#[derive(Debug)]
+enum TrackerState {
+    Op,
+    Params {
+        literal: bool,
+        lens: (usize, usize),
+        buf: [u8; 16],
+        got: usize,
+    },
+    Literal {
+        len: u64,
+        left: u64,
+    },
+    Ended,
+}
+
+impl CommandTracker {
+    // Creates a tracker for a delta starting with its magic number.
+    pub fn new() -> Self {
+        CommandTracker {
+            header_left: DELTA_MAGIC.len(),
+            state: TrackerState::Op,
+            boundary: (0, 0),
+            out_pos: 0,
+        }
+    }
+
+    // Creates a tracker for commands appended to a partial delta, at the given boundary.
+    pub fn resume(out_len: u64, in_len: u64) -> Self {
+        CommandTracker {
+            header_left: 0,
+            state: TrackerState::Op,
+            boundary: (out_len, in_len),
+            out_pos: out_len,
+        }
+    }
+
+    // Returns the delta and new file lengths at the end of the last complete command.
+    pub fn boundary(&self) -> (u64, u64) {
+        self.boundary
+    }
+
+    pub fn update(&mut self, mut data: &[u8]) {
+        while !data.is_empty() {
+            let consumed = self.step(data);
+            data = &data[consumed..];
+        }
+    }
+
+    // Follows the given data until the next state change, returning the number of bytes used.
+    fn step(&mut self, data: &[u8]) -> usize {
+        if self.header_left > 0 {
+            let len = cmp::min(self.header_left, data.len());
+            self.header_left -= len;
+            self.out_pos += len as u64;
+            if self.header_left == 0 {
+                self.boundary = (self.out_pos, 0);
+            }
+            return len;
+        }
+        let (len, ended) = match self.state {
+            TrackerState::Op => {
+                self.state = op_state(data[0]);
+                (1, None)
+            }
+            TrackerState::Params {
+                literal,
+                lens,
+                ref mut buf,
+                ref mut got,
+            } => {
+                let needed = lens.0 + lens.1;
+                let len = cmp::min(needed - *got, data.len());
+                buf[*got..*got + len].copy_from_slice(&data[..len]);
+                *got += len;
+                if *got < needed {
+                    (len, None)
+                } else if literal {
+                    let lit_len = read_int(&buf[..lens.0]);
+                    self.state = TrackerState::Literal {
+                        len: lit_len,
+                        left: lit_len,
+                    };
+                    (len, None)
+                } else {
+                    (len, Some(read_int(&buf[lens.0..needed])))
+                }
+            }
+            TrackerState::Literal { len, ref mut left } => {
+                let consumed = cmp::min(*left, data.len() as u64);
+                *left -= consumed;
+                (consumed as usize, if *left == 0 { Some(len) } else { None })
+            }
+            TrackerState::Ended => (data.len(), None),
+        };
+        self.out_pos += len as u64;
+        if let Some(in_len) = ended {
+            self.boundary = (self.out_pos, self.boundary.1 + in_len);
+            self.state = TrackerState::Op;
+        }
+        len
+    }
+}
+
+// Returns the state following the given command byte.
+fn op_state(op: u8) -> TrackerState {
+    match op {
+        1..=OP_LITERAL_64 => TrackerState::Literal {
+            len: u64::from(op),
+            left: u64::from(op),
+        },
+        OP_LITERAL_N1..=0x44 => TrackerState::Params {
+            literal: true,
+            lens: (INT_LENS[usize::from(op - OP_LITERAL_N1)], 0),
+            buf: [0; 16],
+            got: 0,
+        },
+        OP_COPY_N1_N1..=OP_COPY_N8_N8 => {
+            let kind = usize::from(op - OP_COPY_N1_N1);
+            TrackerState::Params {
+                literal: false,
+                lens: (INT_LENS[kind / 4], INT_LENS[kind % 4]),
+                buf: [0; 16],
+                got: 0,
+            }
+        }
+        // after the end, or in a corrupt delta, no more boundaries can be found
+        _ => TrackerState::Ended,
+    }
+}
+
+fn read_int(buf: &[u8]) -> u64 {
+    buf.iter().fold(0, |acc, &b| (acc << 8) | u64::from(b))
+}
+
+// Writes to an output stream, while following the commands of the written delta.
+pub struct TrackingWriter<'a, W: ?Sized + 'a> {
+    pub inner: &'a mut W,
+    pub tracker: &'a mut CommandTracker,
+}
+
+impl<'a, W: Write + ?Sized> Write for TrackingWriter<'a, W> {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        let written = self.inner.write(buf)?;
+        self.tracker.update(&buf[..written]);
+        Ok(written)
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        self.inner.flush()
+    }
+}
@@ -0,0 +1,166 @@
+use std::io::{self, Write};
+
+use crate::{raw, Error, Result};
+
+/// A strong hash algorithm, among the ones used by librsync for signatures.
+#[derive(Clone, Copy, Debug, Eq, PartialEq, Hash, PartialOrd, Ord)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+#[cfg_attr(feature = "serde", serde(rename_all = "lowercase"))]
+pub enum DigestKind {
+    /// The MD4 hash, producing 16 bytes digests.
+    ///
+    /// It is deprecated because of a security vulnerability.
+    MD4,
+    /// The BLAKE2b hash, producing 32 bytes digests.
+    Blake2,
+}
+
+/// A digest computed with a strong hash algorithm.
+#[derive(Clone, Debug, Eq, PartialEq, Hash)]
+pub struct Digest {
+    kind: DigestKind,
+    bytes: Vec<u8>,
+}
+
+// Computes a digest incrementally, by using the hash functions bundled with librsync.
+pub enum Hasher {
+    MD4(Md4State),
+    Blake2(Blake2State),
+}
+
+// The hash states, allocated by librsync-sys, since their layouts are private to librsync.
+pub struct Md4State(*mut raw::rs_mdfour_t);
+pub struct Blake2State(*mut raw::blake2b_state);
+
+// Writes to an output stream, while computing the digest of the written data.
+pub struct HashWriter<'a, W: ?Sized + 'a> {
+    output: &'a mut W,
+    hasher: Hasher,
+}
+
+const BLAKE2_LEN: usize = 32;
+const MD4_LEN: usize = 16;
+
+impl DigestKind {
+    /// Returns the length of the digests, in bytes.
+    pub fn digest_len(self) -> usize {
+        match self {
+            DigestKind::MD4 => MD4_LEN,
+            DigestKind::Blake2 => BLAKE2_LEN,
+        }
+    }
+}
+
+impl Digest {
+    /// Creates a digest from its raw bytes.
+    pub fn new(kind: DigestKind, bytes: Vec<u8>) -> Self {
+        Digest { kind, bytes }
+    }
+
+    /// Returns the hash algorithm used to compute this digest.
+    pub fn kind(&self) -> DigestKind {
+        self.kind
+    }
+
+    /// Returns the raw bytes of this digest.
+    pub fn as_bytes(&self) -> &[u8] {
+        &self.bytes
+    }
+
+    /// Unwraps this digest, returning its raw bytes.
+    pub fn into_bytes(self) -> Vec<u8> {
+        self.bytes
+    }
+}
+
+impl Hasher {
+    pub fn new(kind: DigestKind) -> Result<Self> {
+        unsafe {
+            match kind {
+                DigestKind::MD4 => {
+                    let md = raw::librsync_sys_mdfour_new();
+                    if md.is_null() {
+                        return Err(Error::Mem);
+                    }
+                    Ok(Hasher::MD4(Md4State(md)))
+                }
+                DigestKind::Blake2 => {
+                    let state = raw::librsync_sys_blake2b_new(BLAKE2_LEN);
+                    if state.is_null() {
+                        return Err(Error::Mem);
+                    }
+                    Ok(Hasher::Blake2(Blake2State(state)))
+                }
+            }
+        }
+    }
+
+    pub fn update(&mut self, data: &[u8]) {
+        unsafe {
+            match *self {
+                Hasher::MD4(ref md) => raw::rs_mdfour_update(md.0, data.as_ptr() as _, data.len()),
+                Hasher::Blake2(ref state) => {
+                    raw::librsync_sys_blake2b_update(state.0, data.as_ptr() as _, data.len());
+                }
+            }
+        }
+    }
+
+    pub fn finish(self) -> Digest {
+        unsafe {
+            match self {
+                Hasher::MD4(md) => {
+                    let mut bytes = vec![0; MD4_LEN];
+                    raw::rs_mdfour_result(md.0, bytes.as_mut_ptr());
+                    Digest::new(DigestKind::MD4, bytes)
+                }
+                Hasher::Blake2(state) => {
+                    let mut bytes = vec![0; BLAKE2_LEN];
+                    raw::librsync_sys_blake2b_final(state.0, bytes.as_mut_ptr() as _, BLAKE2_LEN);
+                    Digest::new(DigestKind::Blake2, bytes)
+                }
+            }
+        }
+    }
+}
+
+impl Drop for Md4State {
+    fn drop(&mut self) {
+        unsafe {
+            raw::librsync_sys_mdfour_free(self.0);
+        }
+    }
+}
+
+impl Drop for Blake2State {
+    fn drop(&mut self) {
+        unsafe {
+            raw::librsync_sys_blake2b_free(self.0);
+        }
+    }
+}
+
+impl<'a, W: Write + ?Sized> HashWriter<'a, W> {
+    pub fn new(output: &'a mut W, kind: DigestKind) -> Result<Self> {
+        Ok(HashWriter {
+            output,
+            hasher: Hasher::new(kind)?,
+        })
+    }
+
+    pub fn finish(self) -> Digest {
+        self.hasher.finish()
+    }
+}
+
+impl<'a, W: Write + ?Sized> Write for HashWriter<'a, W> {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        let written = self.output.write(buf)?;
+        self.hasher.update(&buf[..written]);
+        Ok(written)
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        self.output.flush()
+    }
+}
@@ -1,17 +1,52 @@
-use std::io::{self, BufRead, Read};
+use std::cmp;
+use std::io::{self, BufRead, Read, Write};
 use std::marker::PhantomData;
 use std::ops::Deref;
 use std::ptr;
+use std::time::Instant;
 
-use crate::{raw, Error};
+use crate::{raw, Error, JobState, Metrics, OpKind, Stats};
 
 pub struct JobDriver<R> {
     input: R,
     job: Job,
     input_ended: bool,
+    done: bool,
+    // the state after the last attempt to produce output
+    state: JobState,
+    iterations: u64,
+    in_bytes: u64,
+    out_bytes: u64,
+    max_out_bytes: u64,
+    deadline: Option<Instant>,
+    metrics: Option<Box<dyn Metrics>>,
+    // the operation to report to the metrics, until the start of the job is reported
+    start_op: Option<OpKind>,
+    // output produced by the job, the bytes between out_pos and out_end are not read yet
+    out_buf: Vec<u8>,
+    out_pos: usize,
+    out_end: usize,
 }
 
-pub struct Job(pub *mut raw::rs_job_t);
+/// Drives a job by pushing the input into it, and writing its output to a `Write` stream.
+pub struct JobWriter<W> {
+    output: W,
+    job: Job,
+    buf: Vec<u8>,
+    in_bytes: u64,
+    out_bytes: u64,
+}
+
+pub struct Job {
+    raw: *mut raw::rs_job_t,
+    // the span of the whole job, entered around each iteration
+    #[cfg(feature = "tracing")]
+    span: tracing::Span,
+}
+
+const BUF_LEN: usize = 8 * 1024;
+pub const SIG_IN_BUF_LEN: usize = BUF_LEN;
+pub const SIG_OUT_BUF_LEN: usize = BUF_LEN;
 
 // Wrapper around rs_buffers_t.
 struct Buffers<'a> {
@@ -21,10 +56,33 @@ struct Buffers<'a> {
 
 impl<R: BufRead> JobDriver<R> {
     pub fn new(input: R, job: Job) -> Self {
+        Self::with_buf_len(input, job, BUF_LEN)
+    }
+
+    pub fn with_buf_len(input: R, job: Job, out_buf_len: usize) -> Self {
+        Self::with_buf(input, job, vec![0; out_buf_len])
+    }
+
+    /// Creates a driver writing the job output into the given buffer.
+    ///
+    /// The whole buffer is used, so it must not be empty.
+    pub fn with_buf(input: R, job: Job, out_buf: Vec<u8>) -> Self {
         JobDriver {
             input,
             job,
             input_ended: false,
+            done: false,
+            state: JobState::Running,
+            iterations: 0,
+            in_bytes: 0,
+            out_bytes: 0,
+            max_out_bytes: u64::MAX,
+            deadline: None,
+            metrics: None,
+            start_op: None,
+            out_buf,
+            out_pos: 0,
+            out_end: 0,
         }
     }
 
@@ -32,6 +90,73 @@ impl<R: BufRead> JobDriver<R> {
         self.input
     }
 
+    /// Returns the input stream, and the output buffer for reuse.
+    pub fn into_parts(self) -> (R, Vec<u8>) {
+        (self.input, self.out_buf)
+    }
+
+    /// Limits the total number of bytes the job is allowed to produce.
+    ///
+    /// Once the limit is crossed, reads fail with an `ErrorKind::InvalidData` error.
+    pub fn set_max_output(&mut self, max_out_bytes: u64) {
+        self.max_out_bytes = max_out_bytes;
+    }
+
+    /// Sets a deadline for the job.
+    ///
+    /// Once the deadline is passed, reads fail with an `ErrorKind::TimedOut` error. It is
+    /// checked before each iteration of the job, so the job is never interrupted in the middle
+    /// of an iteration.
+    pub fn set_deadline(&mut self, deadline: Instant) {
+        self.deadline = Some(deadline);
+    }
+
+    /// Installs a collector of the job events.
+    ///
+    /// The start of the job is reported at the next iteration, unless the job is already done.
+    pub fn set_metrics(&mut self, metrics: Box<dyn Metrics>, op: OpKind) {
+        self.metrics = Some(metrics);
+        self.start_op = Some(op);
+    }
+
+    /// Returns the number of times the job has been iterated.
+    #[cfg(test)]
+    pub fn iterations(&self) -> u64 {
+        self.iterations
+    }
+
+    /// Returns the number of input bytes consumed by the job.
+    pub fn in_bytes(&self) -> u64 {
+        self.in_bytes
+    }
+
+    /// Returns whether the job has produced all its output.
+    pub fn is_done(&self) -> bool {
+        self.done
+    }
+
+    /// Returns the state of the job after the last attempt to produce output.
+    pub fn state(&self) -> JobState {
+        self.state
+    }
+
+    /// Returns the number of bytes produced by the job, but not read yet.
+    pub fn pending_output_len(&self) -> usize {
+        self.out_end - self.out_pos
+    }
+
+    /// Returns the job statistics.
+    ///
+    /// librsync only counts the input and output bytes when it works on files, so they are
+    /// tracked here instead.
+    pub fn stats(&self) -> Stats {
+        Stats {
+            in_bytes: self.in_bytes,
+            out_bytes: self.out_bytes,
+            ..self.job.stats()
+        }
+    }
+
     /// Complete the job by working without an output buffer.
     ///
     /// If the job needs to write some data, an `ErrorKind::WouldBlock` error is returned.
@@ -46,12 +171,13 @@ impl<R: BufRead> JobDriver<R> {
 
                 // work
                 let mut buffers = Buffers::with_no_out(readbuf, self.input_ended);
-                let res = unsafe { raw::rs_job_iter(*self.job, buffers.as_raw()) };
+                let res = self.job.iter(&mut buffers);
                 let read = cap - buffers.available_input();
                 (res, read, cap - read)
             };
             // update read size
             self.input.consume(read);
+            self.in_bytes += read as u64;
 
             // determine result
             // NOTE: this should be done here, after the input buffer update, because we need to
@@ -68,10 +194,7 @@ impl<R: BufRead> JobDriver<R> {
                         ));
                     }
                 }
-                _ => {
-                    let err = Error::from(res);
-                    return Err(io::Error::new(io::ErrorKind::Other, err));
-                }
+                _ => return Err(job_error(res)),
             };
 
             if self.input_ended {
@@ -81,12 +204,38 @@ impl<R: BufRead> JobDriver<R> {
     }
 }
 
-impl<R: BufRead> Read for JobDriver<R> {
-    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
-        let mut out_pos = 0;
-        let mut out_cap = buf.len();
+impl<R: BufRead> JobDriver<R> {
+    // Works until some output is produced, or the job is done, and updates the job state.
+    fn fill_output(&mut self) -> io::Result<()> {
+        let res = self.work();
+        self.state = match res {
+            Ok(()) if self.done => JobState::Done,
+            Ok(()) => JobState::Running,
+            Err(ref e) if e.kind() == io::ErrorKind::WouldBlock => JobState::NeedMoreInput,
+            Err(_) => JobState::Errored,
+        };
+        res
+    }
 
-        loop {
+    // Works until some output is produced, or the job is done.
+    //
+    // This returns as soon as some output is available, instead of blocking on the input to fill
+    // the whole output buffer.
+    fn work(&mut self) -> io::Result<()> {
+        self.out_pos = 0;
+        self.out_end = 0;
+        while !self.done && self.out_end == 0 {
+            if self.out_bytes > self.max_out_bytes {
+                return Err(io::Error::new(
+                    io::ErrorKind::InvalidData,
+                    "output exceeds maximum",
+                ));
+            }
+            if let Some(deadline) = self.deadline {
+                if Instant::now() >= deadline {
+                    return Err(io::Error::new(io::ErrorKind::TimedOut, "deadline exceeded"));
+                }
+            }
             let (res, read, written) = {
                 let readbuf = self.input.fill_buf()?;
                 let cap = readbuf.len();
@@ -95,26 +244,198 @@ impl<R: BufRead> Read for JobDriver<R> {
                 }
 
                 // work
-                let mut buffers = Buffers::new(readbuf, &mut buf[out_pos..], self.input_ended);
-                let res = unsafe { raw::rs_job_iter(*self.job, buffers.as_raw()) };
-                if res != raw::RS_DONE && res != raw::RS_BLOCKED {
-                    let err = Error::from(res);
-                    return Err(io::Error::new(io::ErrorKind::Other, err));
+                // produce at most one byte past the limit, to detect when it is crossed
+                let remaining = self.max_out_bytes - self.out_bytes;
+                let out_cap = cmp::min(self.out_buf.len() as u64, remaining.saturating_add(1));
+                let out_cap = out_cap as usize;
+                let out_buf = &mut self.out_buf[..out_cap];
+                let mut buffers = Buffers::new(readbuf, out_buf, self.input_ended);
+                if let Some(ref mut metrics) = self.metrics {
+                    if let Some(op) = self.start_op.take() {
+                        metrics.on_start(op);
+                    }
+                    metrics.on_iteration();
                 }
+                let res = self.job.iter(&mut buffers);
+                self.iterations += 1;
                 let read = cap - buffers.available_input();
                 let written = out_cap - buffers.available_output();
                 (res, read, written)
             };
 
-            // update read size
+            // update read size, even on errors, to report the input processed until then
             self.input.consume(read);
+            self.in_bytes += read as u64;
+            if res != raw::RS_DONE && res != raw::RS_BLOCKED {
+                return Err(job_error(res));
+            }
             // update write size
-            out_pos += written;
-            out_cap -= written;
-            if out_cap == 0 || res == raw::RS_DONE {
-                return Ok(out_pos);
+            self.out_bytes += written as u64;
+            self.out_end = written;
+            self.done = res == raw::RS_DONE;
+            if self.done && self.metrics.is_some() {
+                let stats = self.stats();
+                if let Some(ref mut metrics) = self.metrics {
+                    metrics.on_done(&stats);
+                }
+            }
+        }
+        if self.out_bytes > self.max_out_bytes {
+            self.out_end = 0;
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidData,
+                "output exceeds maximum",
+            ));
+        }
+        Ok(())
+    }
+}
+
+impl<R: BufRead> JobDriver<R> {
+    /// Writes all the remaining output to the given stream, returning the number of bytes
+    /// written.
+    pub fn copy_to<W: Write + ?Sized>(&mut self, out: &mut W) -> io::Result<u64> {
+        let mut written = 0;
+        loop {
+            if self.out_pos == self.out_end {
+                if self.done {
+                    return Ok(written);
+                }
+                self.fill_output()?;
+                continue;
             }
+            out.write_all(&self.out_buf[self.out_pos..self.out_end])?;
+            written += (self.out_end - self.out_pos) as u64;
+            self.out_pos = self.out_end;
+        }
+    }
+}
+
+impl<R: BufRead> Read for JobDriver<R> {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        if buf.is_empty() {
+            return Ok(0);
+        }
+        if self.out_pos == self.out_end {
+            self.fill_output()?;
         }
+        let len = cmp::min(buf.len(), self.out_end - self.out_pos);
+        buf[..len].copy_from_slice(&self.out_buf[self.out_pos..self.out_pos + len]);
+        self.out_pos += len;
+        Ok(len)
+    }
+}
+
+impl Job {
+    /// Takes ownership of a job started by librsync, which is freed on drop.
+    pub fn new(job: *mut raw::rs_job_t) -> Self {
+        Job {
+            raw: job,
+            #[cfg(feature = "tracing")]
+            span: Self::span(job),
+        }
+    }
+
+    pub fn stats(&self) -> Stats {
+        unsafe { Stats::from_raw(&*raw::rs_job_statistics(self.raw)) }
+    }
+
+    /// Runs the job on the given buffers, until it is blocked or done.
+    fn iter(&self, buffers: &mut Buffers) -> raw::rs_result {
+        #[cfg(feature = "tracing")]
+        let _entered = self.span.enter();
+        unsafe { raw::rs_job_iter(self.raw, buffers.as_raw()) }
+    }
+
+    // Creates a span identified by the job operation, like "delta" or "patch".
+    #[cfg(feature = "tracing")]
+    fn span(job: *mut raw::rs_job_t) -> tracing::Span {
+        use std::ffi::CStr;
+
+        if job.is_null() {
+            return tracing::Span::none();
+        }
+        let op = unsafe { CStr::from_ptr((*raw::rs_job_statistics(job)).op) };
+        tracing::debug_span!(target: "librsync", "job", op = %op.to_string_lossy())
+    }
+}
+
+impl<W: Write> JobWriter<W> {
+    pub fn new(output: W, job: Job) -> Self {
+        JobWriter {
+            output,
+            job,
+            buf: vec![0; BUF_LEN],
+            in_bytes: 0,
+            out_bytes: 0,
+        }
+    }
+
+    /// Returns the job statistics, with the input and output bytes tracked like `JobDriver`.
+    pub fn stats(&self) -> Stats {
+        Stats {
+            in_bytes: self.in_bytes,
+            out_bytes: self.out_bytes,
+            ..self.job.stats()
+        }
+    }
+
+    /// Signals the end of the input and drives the job to completion.
+    pub fn finish(mut self) -> io::Result<W> {
+        loop {
+            let (_, _, res) = self.iterate(&[], true)?;
+            if res == raw::RS_DONE {
+                self.output.flush()?;
+                return Ok(self.output);
+            }
+        }
+    }
+
+    // Runs a single iteration of the job, and writes out the produced output.
+    //
+    // This returns the number of bytes read from the input and written to the output.
+    fn iterate(
+        &mut self,
+        input: &[u8],
+        eof_in: bool,
+    ) -> io::Result<(usize, usize, raw::rs_result)> {
+        let (res, read, written) = {
+            let out_cap = self.buf.len();
+            let mut buffers = Buffers::new(input, &mut self.buf, eof_in);
+            let res = self.job.iter(&mut buffers);
+            if res != raw::RS_DONE && res != raw::RS_BLOCKED {
+                return Err(job_error(res));
+            }
+            let read = input.len() - buffers.available_input();
+            let written = out_cap - buffers.available_output();
+            (res, read, written)
+        };
+        self.in_bytes += read as u64;
+        self.output.write_all(&self.buf[..written])?;
+        self.out_bytes += written as u64;
+        Ok((read, written, res))
+    }
+}
+
+impl<W: Write> Write for JobWriter<W> {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        if buf.is_empty() {
+            return Ok(0);
+        }
+        let mut consumed = 0;
+        while consumed < buf.len() {
+            let (read, written, res) = self.iterate(&buf[consumed..], false)?;
+            consumed += read;
+            // an iteration may only flush pending output, so stop only without any progress
+            if res == raw::RS_DONE || (read == 0 && written == 0) {
+                break;
+            }
+        }
+        Ok(consumed)
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        self.output.flush()
     }
 }
 
@@ -123,20 +444,25 @@ unsafe impl Send for Job {}
 impl Deref for Job {
     type Target = *mut raw::rs_job_t;
     fn deref(&self) -> &Self::Target {
-        &self.0
+        &self.raw
     }
 }
 
 impl Drop for Job {
     fn drop(&mut self) {
         unsafe {
-            if !self.0.is_null() {
-                raw::rs_job_free(self.0);
+            if !self.raw.is_null() {
+                raw::rs_job_free(self.raw);
             }
         }
     }
 }
 
+// Wraps a job failure into an IO error.
+fn job_error(res: raw::rs_result) -> io::Error {
+    io::Error::new(io::ErrorKind::Other, Error::from(res))
+}
+
 impl<'a> Buffers<'a> {
     pub fn new(in_buf: &'a [u8], out_buf: &'a mut [u8], eof_in: bool) -> Self {
         Buffers {
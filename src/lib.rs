@@ -107,33 +107,131 @@ extern crate librsync_sys as raw;
 #[macro_use]
 extern crate log;
 
+mod cache;
+mod chunk;
+mod command;
+mod digest;
 mod job;
 mod logfwd;
 mod macros;
+mod metrics;
+mod multi;
 pub mod whole;
 
-use crate::job::{Job, JobDriver};
+pub use crate::cache::CachingBase;
+pub use crate::chunk::{ChunkReader, ChunkedBase};
+pub use crate::command::{Command, DeltaCommands};
+use crate::command::{CommandTracker, TrackingWriter};
+pub use crate::digest::{Digest, DigestKind};
+pub use crate::metrics::{Metrics, OpKind};
+pub use crate::multi::{MultiReader, MultiSeekReader};
+
+use crate::job::{Job, JobDriver, JobWriter};
 
 use std::cell::{RefCell, RefMut};
+use std::cmp;
+use std::convert::TryFrom;
 use std::error;
 use std::fmt::{self, Display, Formatter};
-use std::io::{self, BufRead, BufReader, Read, Seek};
+use std::io::{self, BufRead, BufReader, Read, Seek, Write};
 use std::mem;
 use std::ops::Deref;
+use std::panic::{self, AssertUnwindSafe};
 use std::ptr;
 use std::rc::Rc;
 use std::slice;
+use std::str::FromStr;
+use std::sync::OnceLock;
+use std::time::Instant;
 
-/// The signature type.
-#[derive(Clone, Copy, Debug, Eq, PartialEq)]
-pub enum SignatureType {
-    /// A signature file with MD4 signatures.
+/// The rolling checksum of a signature, used to find matching blocks at any offset.
+#[derive(Clone, Copy, Debug, Eq, PartialEq, Hash, PartialOrd, Ord)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+#[cfg_attr(feature = "serde", serde(rename_all = "lowercase"))]
+pub enum RollingKind {
+    /// The classic rsync rolling checksum.
+    Rollsum,
+    /// The RabinKarp rolling hash, faster and with fewer collisions.
     ///
-    /// Backward compatible with librsync < 1.0, but deprecated because of a security
-    /// vulnerability.
-    MD4,
-    /// A signature file using BLAKE2 hash.
-    Blake2,
+    /// It requires librsync >= 2.2.
+    RabinKarp,
+}
+
+/// The signature type.
+///
+/// A signature type combines a rolling checksum, used to find candidate matching blocks, with a
+/// strong hash, used to confirm them. The most common types are provided as the `MD4` and
+/// `Blake2` constants, and the others can be created with `new`. Signature types are ordered by
+/// their magic number, so MD4 sorts before BLAKE2, and the classic rolling checksum before
+/// RabinKarp.
+///
+/// With the `serde` feature, signature types are serialized as their names, like `blake2`.
+#[derive(Clone, Copy, Debug, Eq, PartialEq, Hash, PartialOrd, Ord)]
+pub struct SignatureType {
+    rolling: RollingKind,
+    digest: DigestKind,
+}
+
+/// Statistics about the work done by a librsync job.
+///
+/// They are collected by librsync while a signature, delta or patch is computed, and can be
+/// retrieved at any time through the `stats` method of the streaming types.
+#[derive(Clone, Copy, Debug, Default, Eq, PartialEq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct Stats {
+    /// Number of literal commands.
+    pub lit_cmds: u64,
+    /// Number of literal bytes.
+    pub lit_bytes: u64,
+    /// Number of bytes used in literal command headers.
+    pub lit_cmdbytes: u64,
+    /// Number of copy commands.
+    pub copy_cmds: u64,
+    /// Number of bytes copied from the basis file.
+    pub copy_bytes: u64,
+    /// Number of bytes used in copy command headers.
+    pub copy_cmdbytes: u64,
+    /// Number of signature commands.
+    pub sig_cmds: u64,
+    /// Number of signature bytes.
+    pub sig_bytes: u64,
+    /// Number of weak checksum matches rejected by the strong checksum.
+    pub false_matches: u64,
+    /// Number of blocks described by the signature.
+    pub sig_blocks: u64,
+    /// The block length of the signature, in bytes.
+    pub block_len: usize,
+    /// Total bytes read from input.
+    pub in_bytes: u64,
+    /// Total bytes written to output.
+    pub out_bytes: u64,
+}
+
+/// The parameters a signature has been generated with.
+///
+/// This information is stored in the header of every signature, and can be read back by using
+/// the `parse` constructor. It can be used as a map key, to cache signatures by their
+/// parameters; the ordering compares the signature type first, then the block length and the
+/// strong signature length.
+#[derive(Clone, Copy, Debug, Eq, PartialEq, Hash, PartialOrd, Ord)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct SignatureInfo {
+    /// The signature format.
+    pub sig_type: SignatureType,
+    /// The size of checksum blocks, in bytes.
+    pub block_len: usize,
+    /// The size of strong signatures, in bytes.
+    pub strong_len: usize,
+}
+
+/// The checksums of a block of the basis file, as stored in a signature.
+#[derive(Clone, Debug, Eq, PartialEq, Hash)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct BlockSig {
+    /// The weak, rolling checksum of the block.
+    pub weak_sum: u32,
+    /// The strong checksum of the block, truncated to the signature `strong_len`.
+    pub strong_sum: Vec<u8>,
 }
 
 /// Enumeration of all possible errors in this crate.
@@ -144,35 +242,198 @@ pub enum Error {
     /// Out of memory.
     Mem,
     /// Bad magic number at start of stream.
+    ///
+    /// This is reported when the magic number is not known.
     BadMagic,
+    /// The signature uses a weak checksum.
+    ///
+    /// This error is not reported by librsync, but by `Delta::new_strict`, when it is given a
+    /// signature with MD4 strong checksums.
+    WeakChecksum,
+    /// The data is corrupt.
+    ///
+    /// This is reported when librsync finds an unbelievable value in its input, or when some
+    /// data does not match its expected digest. The input should be obtained again.
+    Corrupt,
     /// The feature is not available yet.
+    ///
+    /// This is also reported by `SignatureType::new` for combinations of rolling checksum and
+    /// strong hash which have no signature format.
     Unimplemented,
     /// Probably a library bug.
     Internal,
+    /// A parameter is invalid.
+    ///
+    /// This is reported when a parameter given to this crate is out of range, like a too long
+    /// `strong_len`, or does not match other parameters. `name` is the name of the offending
+    /// parameter, and `detail` explains the problem. librsync parameter errors are reported
+    /// with the `unknown` name, since librsync does not tell which parameter is wrong.
+    InvalidParameter {
+        /// The name of the invalid parameter.
+        name: &'static str,
+        /// What is wrong with the parameter.
+        detail: String,
+    },
     /// All the other error numbers.
     ///
-    /// This error should never occur, as it is an indication of a bug.
+    /// This error should never occur, as it is an indication of a bug. Every error code of
+    /// librsync has its own variant, so this is only used for the result codes which are not
+    /// errors (`RS_DONE`, `RS_RUNNING` and `RS_TEST_SKIPPED`), and for codes unknown to this
+    /// crate.
     Unknown(i32),
 }
 
+/// The classification of an `Error`, without its payload.
+///
+/// It is returned by `Error::kind`, to branch on the cause of an error without matching the
+/// payload of the `Io` variant. The numeric value of every kind, given by `as i32`, is stable,
+/// and can be passed across an FFI boundary. More kinds may be added in the future.
+#[derive(Clone, Copy, Debug, Eq, PartialEq, Hash)]
+#[non_exhaustive]
+pub enum ErrorKind {
+    /// An IO error, including the ones reported by librsync for its input and output.
+    Io = 1,
+    /// A syntax error in a signature or in a delta, reported by librsync.
+    Syntax = 2,
+    /// Out of memory.
+    Mem = 3,
+    /// Bad magic number at start of stream.
+    BadMagic = 4,
+    /// The feature is not available yet.
+    Unimplemented = 5,
+    /// Probably a library bug.
+    Internal = 6,
+    /// The data is corrupt.
+    Corrupt = 7,
+    /// All the other error numbers.
+    Unknown = 8,
+    /// The signature uses a weak checksum.
+    WeakChecksum = 9,
+    /// A parameter is invalid.
+    InvalidParameter = 10,
+}
+
 /// A `Result` type alias for this crate's `Error` type.
 pub type Result<T> = std::result::Result<T, Error>;
 
+/// The state of the job underlying a stream, after the last read.
+///
+/// It tells apart the reasons why a read returned, when the input is non-blocking: a read
+/// failing with `io::ErrorKind::WouldBlock` leaves the job waiting for more input, while a read
+/// returning zero bytes once the job is done marks the end of the stream.
+#[derive(Clone, Copy, Debug, Eq, PartialEq, Hash)]
+pub enum JobState {
+    /// The job is working, and may produce more output.
+    Running,
+    /// The last read stopped because the input had no data available yet.
+    NeedMoreInput,
+    /// The job has produced all its output.
+    Done,
+    /// The last read failed, either reading the input or in librsync.
+    Errored,
+}
+
 /// A struct to generate a signature.
 ///
 /// This type takes a `Read` stream for the input from which compute the signatures, and implements
 /// another `Read` stream from which get the result.
 pub struct Signature<R> {
     driver: JobDriver<R>,
+    progress: Option<BlockProgress>,
+    #[cfg(feature = "output-trace")]
+    trace: Option<Box<dyn FnMut(&[u8]) + Send>>,
+}
+
+/// A builder for signature streams.
+///
+/// It collects the parameters of a signature, starting from the same defaults used by
+/// `Signature::new`, and builds `Signature` streams out of them. Since it only stores plain
+/// parameters, it can be copied and compared, to remember how the signature of a basis file has
+/// been generated.
+///
+/// The builder also allows to tune the sizes of the buffers exchanged with librsync. The input
+/// buffer holds the data read from the basis file, while the output buffer receives the
+/// signature produced by librsync. Larger buffers reduce the number of calls into librsync, at
+/// the cost of memory, and a larger output buffer delays the first bytes of output for slow
+/// inputs. Since a signature is much smaller than its input, the default output buffer is
+/// rarely a bottleneck.
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub struct SignatureBuilder {
+    block_len: usize,
+    strong_len: usize,
+    sig_type: SignatureType,
+    in_buf_len: usize,
+    out_buf_len: usize,
+}
+
+/// A struct to generate a signature by pushing the input into it.
+///
+/// This type is the push-based counterpart of `Signature`: it implements `Write`, so the basis
+/// file can be written into it, and writes the resulting signature into another `Write` stream.
+/// Since a writer has no natural end of input, `finish` must be called once all the input has
+/// been written, otherwise the signature is left incomplete.
+pub struct SignatureWriter<W> {
+    writer: JobWriter<W>,
+}
+
+/// A struct to generate a signature of a stream, while reading it.
+///
+/// This type takes a `Read` stream and implements another `Read` stream, which yields the very
+/// same bytes. The signature of the bytes read through it is computed on the side, and returned
+/// by `finish`. This allows, for example, to upload a file and compute its signature in a single
+/// pass.
+pub struct SignatureTee<R> {
+    input: R,
+    sig: SignatureWriter<Vec<u8>>,
 }
 
 /// A struct to generate a delta between two files.
 ///
 /// This type takes two `Read` streams, one for the signature of the base file and one for the new
 /// file. It then provides another `Read` stream from which get the result.
+///
+/// A delta can be moved to another thread, but it is not `Sync`, and the signature it loads
+/// cannot be shared with other deltas, even if it is not modified after its hash table is built:
+/// librsync updates the statistics kept in the signature and in the hash table at each lookup,
+/// so that concurrent deltas would race on them. Every delta loads its own copy of the
+/// signature instead.
+///
+/// ```compile_fail
+/// fn assert_sync<T: Sync>() {}
+/// assert_sync::<librsync::Delta<std::io::Empty>>();
+/// ```
 pub struct Delta<R> {
     driver: JobDriver<R>,
     _sumset: Sumset,
+    tracker: CommandTracker,
+    progress: Option<FractionProgress>,
+}
+
+/// A delta stream loading the signature on first use.
+///
+/// `Delta::new` loads the whole signature before returning. This type instead takes ownership
+/// of the signature stream, and returns immediately: the signature is loaded the first time the
+/// delta is read. This allows to set up a pipeline before the signature is available, for
+/// example while it is still being received. Note that librsync cannot compute a delta from a
+/// partial signature, so the first read still waits for the whole signature to be loaded,
+/// before producing any delta byte.
+pub struct LazyDelta<R, S> {
+    new: Option<R>,
+    base_sig: S,
+    delta: Option<Delta<BufReader<R>>>,
+}
+
+/// A point from which an interrupted delta computation can be resumed.
+///
+/// It is taken with `Delta::checkpoint`, and describes a prefix of the delta which is complete,
+/// together with the part of the new file it describes. See `Delta::resume`.
+#[derive(Clone, Copy, Debug, Default, Eq, PartialEq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct Checkpoint {
+    /// The number of bytes of the new file described by the delta prefix.
+    pub input_offset: u64,
+    /// The length of the delta prefix, in bytes.
+    pub output_len: u64,
 }
 
 /// A struct to apply a delta to a basis file, to recreate the new file.
@@ -181,16 +442,66 @@ pub struct Delta<R> {
 /// file. It then provides another `Read` stream from which get the resulting patched file.
 pub struct Patch<'a, B: 'a, D> {
     driver: JobDriver<D>,
-    base: Rc<RefCell<B>>,
-    raw: Box<Rc<RefCell<dyn ReadAndSeek + 'a>>>,
+    base: Rc<RefCell<PositionedBase<B>>>,
+    raw: Box<CopySource<'a>>,
+}
+
+/// A basis file opened on first use.
+///
+/// This type is used by `Patch::new_lazy` to defer opening the basis file until the delta needs
+/// to copy some data from it.
+pub struct LazyBase<B, F> {
+    open: Option<F>,
+    base: Option<B>,
 }
 
+// A signature loaded by librsync, with its hash table.
+//
+// It is owned by a single delta, and it must not be shared among threads, see `Delta`.
 struct Sumset(*mut raw::rs_signature_t);
 
+/// A stream which is both `Read` and `Seek`.
+///
+/// This trait allows to use trait objects of seekable streams, like the segments of a
+/// `MultiSeekReader`, and is implemented by every type implementing both traits.
 // workaround for E0225
-trait ReadAndSeek: Read + Seek {}
+pub trait ReadAndSeek: Read + Seek {}
 impl<T: Read + Seek> ReadAndSeek for T {}
 
+// The data given to the patch copy callback.
+struct CopySource<'a> {
+    base: Rc<RefCell<dyn ReadAndSeek + 'a>>,
+    // the number of times a transient error reading the base is retried
+    retries: usize,
+    // called with the offset and the length of every read of the base
+    #[cfg(feature = "copy-hook")]
+    on_copy: RefCell<Option<Box<dyn FnMut(u64, usize) + 'a>>>,
+    // the regions of the base read so far, if recorded
+    copies: RefCell<Option<Vec<Command>>>,
+}
+
+// Wraps the patch base, keeping track of its position to skip the seeks requested by sequential
+// copy commands, which would otherwise be issued before every read.
+struct PositionedBase<B> {
+    inner: B,
+    pos: Option<u64>,
+}
+
+// Reports the progress of a signature in blocks, from the input consumed by its job.
+struct BlockProgress {
+    block_len: u64,
+    total: Option<u64>,
+    done: u64,
+    callback: Box<dyn FnMut(u64, Option<u64>) + Send>,
+}
+
+// Reports the progress of a delta as the fraction of the new file consumed by its job.
+struct FractionProgress {
+    new_len: u64,
+    last: f64,
+    callback: Box<dyn FnMut(f64) + Send>,
+}
+
 impl<R: Read> Signature<BufReader<R>> {
     /// Creates a new signature stream with default parameters.
     ///
@@ -207,7 +518,8 @@ impl<R: Read> Signature<BufReader<R>> {
     /// size of checksum blocks as `block_len` parameter (larger values make the signature shorter
     /// and the delta longer), and the size of strong signatures in bytes as `strong_len`
     /// parameter. If it is non-zero the signature will be truncated to that amount of bytes.
-    /// The last parameter specifies which version of the signature format to be used.
+    /// The last parameter specifies which version of the signature format to be used. A zero
+    /// `block_len` selects the default block length of 2048 bytes.
     pub fn with_options(
         input: R,
         block_len: usize,
@@ -216,6 +528,128 @@ impl<R: Read> Signature<BufReader<R>> {
     ) -> Result<Self> {
         Self::with_buf_read(BufReader::new(input), block_len, strong_len, sig_magic)
     }
+
+    /// Creates a new signature stream, using the given buffer for the output of librsync.
+    ///
+    /// This constructor behaves like `SignatureBuilder::build`, but adopts the `scratch` buffer
+    /// instead of allocating a new one, resizing it to the output buffer length of the builder.
+    /// The buffer is given back by `into_parts`, so that it can be reused for the next
+    /// signature, avoiding an allocation per signature when many small ones are computed.
+    pub fn new_in(input: R, builder: SignatureBuilder, scratch: Vec<u8>) -> Result<Self> {
+        if builder.in_buf_len == 0 {
+            return Err(invalid_param("in_buf_len", "empty input buffer"));
+        }
+        let input = BufReader::with_capacity(builder.in_buf_len, input);
+        builder.build_in(input, scratch)
+    }
+
+    /// Computes the whole signature of an input, along with its parameters.
+    ///
+    /// The signature of the `input` stream is generated with the parameters of the given
+    /// `builder`, and returned in a `Vec`, together with the `SignatureInfo` read from its
+    /// header. This reports the actual parameters, like the strong signature length chosen by
+    /// librsync when the builder leaves it to zero.
+    pub fn compute(input: R, builder: SignatureBuilder) -> Result<(Vec<u8>, SignatureInfo)> {
+        let mut sig = Vec::new();
+        builder.build(input)?.read_to_end(&mut sig)?;
+        let info = SignatureInfo::parse(&sig)?;
+        Ok((sig, info))
+    }
+
+    /// Extends a signature with the data appended to the signed file.
+    ///
+    /// Since the blocks of a signature are independent, the signature of a file which only grew
+    /// can be computed by signing the appended data alone. This function takes the previous
+    /// signature (`prev_sig` parameter), the length of the data it was computed on (`prev_len`
+    /// parameter), and the appended data (`new_tail` parameter), and returns the signature of the
+    /// whole file. The other parameters must be the ones used for the previous signature, and
+    /// `prev_len` must be a multiple of `block_len`, so that the appended data starts on a block
+    /// boundary. Otherwise an `Error::InvalidParameter` error is returned.
+    pub fn append(
+        prev_sig: &[u8],
+        prev_len: u64,
+        new_tail: R,
+        block_len: usize,
+        strong_len: usize,
+        sig_magic: SignatureType,
+    ) -> Result<Vec<u8>> {
+        let info = SignatureInfo::parse(prev_sig)?;
+        let strong_len = if strong_len == 0 {
+            sig_magic.max_strong_len()
+        } else {
+            strong_len
+        };
+        if info.sig_type != sig_magic
+            || info.block_len != block_len
+            || info.strong_len != strong_len
+        {
+            return Err(invalid_param(
+                "prev_sig",
+                "signature parameters do not match",
+            ));
+        }
+        if block_len == 0 || prev_len % block_len as u64 != 0 {
+            return Err(invalid_param(
+                "prev_len",
+                "appended data does not start on a block boundary",
+            ));
+        }
+        if sig_magic.signature_len(block_len, strong_len, prev_len) != prev_sig.len() as u64 {
+            return Err(invalid_param(
+                "prev_len",
+                "signature does not match the previous length",
+            ));
+        }
+        let mut sig = prev_sig.to_vec();
+        let mut tail = Self::with_options(new_tail, block_len, strong_len, sig_magic)?;
+        tail.read_exact(&mut [0; SignatureInfo::HEADER_LEN])?;
+        tail.read_to_end(&mut sig)?;
+        Ok(sig)
+    }
+}
+
+impl<R: Read + Seek> Signature<BufReader<io::Take<R>>> {
+    /// Creates a new signature stream over a range of the input.
+    ///
+    /// This constructor seeks the input stream to `offset` and computes the signature of exactly
+    /// the next `len` bytes, ignoring the rest of the input. An `ErrorKind::UnexpectedEof` error
+    /// is returned if the range extends past the end of the input. See `with_options` for
+    /// details on the other parameters.
+    pub fn new_range(
+        mut input: R,
+        offset: u64,
+        len: u64,
+        block_len: usize,
+        strong_len: usize,
+        sig_magic: SignatureType,
+    ) -> Result<Self> {
+        let end = input.seek(io::SeekFrom::End(0))?;
+        if offset > end || len > end - offset {
+            return Err(io_err(
+                io::ErrorKind::UnexpectedEof,
+                "range extends past the end of input",
+            ));
+        }
+        input.seek(io::SeekFrom::Start(offset))?;
+        Self::with_options(input.take(len), block_len, strong_len, sig_magic)
+    }
+}
+
+impl<R: Read + Seek> Signature<BufReader<R>> {
+    /// Unwraps this stream, returning the underlying input stream at the consumed position.
+    ///
+    /// The input stream is read in chunks, so, unless the signature has been read to its end,
+    /// the underlying stream may be positioned past the data actually processed. This method
+    /// seeks it back, right after the last byte processed, so that it can be used by following
+    /// operations. When the input stream is not seekable, `into_inner` returns the buffered
+    /// stream instead, which still holds the data not processed yet.
+    pub fn into_inner_seek(self) -> Result<R> {
+        let input = self.driver.into_inner();
+        let buffered = input.buffer().len() as i64;
+        let mut input = input.into_inner();
+        input.seek(io::SeekFrom::Current(-buffered))?;
+        Ok(input)
+    }
 }
 
 impl<R: BufRead> Signature<R> {
@@ -231,301 +665,3112 @@ impl<R: BufRead> Signature<R> {
         strong_len: usize,
         sig_magic: SignatureType,
     ) -> Result<Self> {
-        logfwd::init();
+        SignatureBuilder::new()
+            .block_len(block_len)
+            .strong_len(strong_len)
+            .sig_type(sig_magic)
+            .build_buf_read(input)
+    }
 
-        let job = unsafe { raw::rs_sig_begin(block_len, strong_len, sig_magic.as_raw()) };
-        if job.is_null() {
-            return Err(Error::BadMagic);
+    /// Reads the whole signature, parsed into the checksums of each block.
+    ///
+    /// This reads the signature header, and then returns the entries for all the blocks, in
+    /// order. It allows to index or compare the checksums of single blocks, without parsing the
+    /// signature format. This method must be called before reading anything from this stream.
+    pub fn read_entries(&mut self) -> Result<Vec<BlockSig>> {
+        let mut header = [0; SignatureInfo::HEADER_LEN];
+        self.read_exact(&mut header)?;
+        let info = SignatureInfo::parse(&header)?;
+        let mut entries = Vec::new();
+        while let Some(entry) = read_block_sig(self, info.strong_len)? {
+            entries.push(entry);
         }
-        Ok(Signature {
-            driver: JobDriver::new(input, Job(job)),
-        })
+        Ok(entries)
     }
 
-    /// Unwraps this stream, returning the underlying input stream.
-    pub fn into_inner(self) -> R {
-        self.driver.into_inner()
+    /// Returns the statistics collected so far by the underlying job.
+    pub fn stats(&self) -> Stats {
+        self.driver.stats()
     }
-}
 
-impl<R: BufRead> Read for Signature<R> {
-    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
-        self.driver.read(buf)
+    /// Installs a collector of the events of the underlying job.
+    ///
+    /// The collector replaces any previously installed one, and receives the events of the job
+    /// from now on. See `Metrics` for the events produced.
+    pub fn with_metrics<M: Metrics + 'static>(mut self, metrics: M) -> Self {
+        self.driver
+            .set_metrics(Box::new(metrics), OpKind::Signature);
+        self
     }
-}
 
-impl<R: Read> Delta<BufReader<R>> {
-    /// Creates a new delta stream.
+    /// Installs a callback reporting the progress in blocks of the input.
     ///
-    /// This constructor takes two `Read` streams for the new file (`new` parameter) and for the
-    /// signatures of the base file (`base_sig` parameter). It produces a delta stream from which
-    /// read the resulting delta file.
-    pub fn new<S: Read + ?Sized>(new: R, base_sig: &mut S) -> Result<Self> {
-        Self::with_buf_read(BufReader::new(new), base_sig)
+    /// The callback is called with the number of blocks processed so far, once for every block,
+    /// as soon as its whole input has been consumed by librsync. The last partial block is
+    /// reported when the job is done. The second argument is the total number of blocks, known
+    /// only if the length of the input is given as `input_len`. The block length must be the one
+    /// this signature has been created with, otherwise no progress is reported if it is zero.
+    pub fn with_block_progress<F>(mut self, block_len: usize, input_len: Option<u64>, f: F) -> Self
+    where
+        F: FnMut(u64, Option<u64>) + Send + 'static,
+    {
+        let block_len = block_len as u64;
+        self.progress = Some(BlockProgress {
+            block_len,
+            total: input_len
+                .filter(|_| block_len > 0)
+                .map(|len| div_ceil(len, block_len)),
+            done: 0,
+            callback: Box::new(f),
+        });
+        self.report_progress();
+        self
     }
-}
 
-impl<R: BufRead> Delta<R> {
-    /// Creates a new delta stream by using a `BufRead` as new file.
+    /// Sets a function observing every chunk of output, as it is read.
     ///
-    /// This constructor specializes the `new` constructor by taking a `BufRead` instance as
-    /// `new` parameter. Prefer this constructor if you already have a `BufRead` as input stream,
-    /// since it avoids wrapping the input stream into another `BufRead` instance. See `new`
-    /// constructor for more details on the parameters.
-    pub fn with_buf_read<S: Read + ?Sized>(new: R, base_sig: &mut S) -> Result<Self> {
-        logfwd::init();
+    /// The function is given the bytes returned by each read, before they reach the caller, to
+    /// debug the wire format exchanged with other rdiff implementations. The output is not
+    /// altered. Any previously set function is replaced. Available with the `output-trace`
+    /// feature only.
+    #[cfg(feature = "output-trace")]
+    pub fn with_trace<F: FnMut(&[u8]) + Send + 'static>(mut self, f: F) -> Self {
+        self.trace = Some(Box::new(f));
+        self
+    }
+
+    /// Returns whether the underlying job has produced all its output.
+    ///
+    /// This reports whether librsync is done producing the output, not whether the output has
+    /// been completely read: when it becomes `true`, some bytes may still be pending (see
+    /// `pending_output_len`), and the stream reaches its end once those are read.
+    pub fn is_done(&self) -> bool {
+        self.driver.is_done()
+    }
+
+    /// Returns the state of the underlying job, after the last read.
+    ///
+    /// This complements `is_done`, by telling a job waiting for more input from a failed one.
+    pub fn state(&self) -> JobState {
+        self.driver.state()
+    }
+
+    /// Returns the number of bytes already produced, but not read yet.
+    pub fn pending_output_len(&self) -> usize {
+        self.driver.pending_output_len()
+    }
 
-        // load the signature
-        let sumset = unsafe {
-            let mut sumset = ptr::null_mut();
-            let job = raw::rs_loadsig_begin(&mut sumset);
-            assert!(!job.is_null());
-            let mut job = JobDriver::new(BufReader::new(base_sig), Job(job));
-            job.consume_input()?;
-            let sumset = Sumset(sumset);
-            let res = raw::rs_build_hash_table(*sumset);
-            if res != raw::RS_DONE {
-                return Err(Error::from(res));
+    /// Writes all the remaining output to the given stream.
+    ///
+    /// This works like `io::copy`, but writes directly from the internal output buffer, without
+    /// an intermediate copy. Any byte already read from this stream is not written again. In
+    /// case of success, the number of bytes written is returned.
+    pub fn copy_to<W: Write + ?Sized>(&mut self, out: &mut W) -> Result<u64> {
+        #[cfg(feature = "output-trace")]
+        {
+            // go through `read`, which traces the output
+            if self.trace.is_some() {
+                return Ok(io::copy(self, out)?);
             }
-            sumset
-        };
-        let job = unsafe { raw::rs_delta_begin(*sumset) };
-        if job.is_null() {
-            return Err(io_err(
-                io::ErrorKind::InvalidData,
-                "invalid signature given",
-            ));
         }
-        Ok(Delta {
-            driver: JobDriver::new(new, Job(job)),
-            _sumset: sumset,
-        })
+        let written = self.driver.copy_to(out);
+        self.report_progress();
+        Ok(written?)
     }
 
-    /// Unwraps this stream, returning the underlying new file stream.
+    /// Unwraps this stream, returning the underlying input stream.
+    ///
+    /// The input stream may hold some data not processed yet, if the signature has not been
+    /// read to its end. See `into_inner_seek` to reposition a seekable stream.
     pub fn into_inner(self) -> R {
         self.driver.into_inner()
     }
+
+    /// Unwraps this stream, returning the underlying input stream and the output buffer.
+    ///
+    /// The buffer can be given to `Signature::new_in`, to be reused by another signature. Its
+    /// content is unspecified.
+    pub fn into_parts(self) -> (R, Vec<u8>) {
+        self.driver.into_parts()
+    }
+
+    fn report_progress(&mut self) {
+        let progress = match self.progress {
+            Some(ref mut progress) if progress.block_len > 0 => progress,
+            _ => return,
+        };
+        let in_bytes = self.driver.in_bytes();
+        let blocks = if self.driver.is_done() {
+            div_ceil(in_bytes, progress.block_len)
+        } else {
+            in_bytes / progress.block_len
+        };
+        while progress.done < blocks {
+            progress.done += 1;
+            (progress.callback)(progress.done, progress.total);
+        }
+    }
 }
 
-impl<R: BufRead> Read for Delta<R> {
+impl<R: BufRead> Read for Signature<R> {
     fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
-        self.driver.read(buf)
+        let read = self.driver.read(buf);
+        self.report_progress();
+        #[cfg(feature = "output-trace")]
+        {
+            if let (Ok(len), Some(trace)) = (&read, &mut self.trace) {
+                trace(&buf[..*len]);
+            }
+        }
+        read
     }
 }
 
-impl<'a, B: Read + Seek + 'a, D: Read> Patch<'a, B, BufReader<D>> {
-    /// Creates a new patch stream.
+impl SignatureBuilder {
+    /// Creates a new builder with default parameters.
     ///
-    /// This constructor takes a `Read + Seek` stream for the basis file (`base` parameter), and a
-    /// `Read` stream for the delta file (`delta` parameter). It produces a stream from which read
-    /// the resulting patched file.
-    pub fn new(base: B, delta: D) -> Result<Self> {
-        Self::with_buf_read(base, BufReader::new(delta))
+    /// BLAKE2 is used for the hashing, 2048 bytes for the block length and full length for the
+    /// strong signature size.
+    pub fn new() -> Self {
+        SignatureBuilder {
+            block_len: raw::RS_DEFAULT_BLOCK_LEN,
+            strong_len: 0,
+            sig_type: SignatureType::Blake2,
+            in_buf_len: job::SIG_IN_BUF_LEN,
+            out_buf_len: job::SIG_OUT_BUF_LEN,
+        }
     }
-}
 
-impl<'a, B: Read + Seek + 'a, D: BufRead> Patch<'a, B, D> {
-    /// Creates a new patch stream by using a `BufRead` as delta stream.
+    /// Sets the size of checksum blocks, in bytes.
     ///
-    /// This constructor specializes the `new` constructor by taking a `BufRead` instance as
-    /// `delta` parameter. Prefer this constructor if you already have a `BufRead` as input
-    /// stream, since it avoids wrapping the input stream into another `BufRead` instance. See
-    /// `new` constructor for more details on the parameters.
-    pub fn with_buf_read(base: B, delta: D) -> Result<Self> {
-        logfwd::init();
-
-        let base = Rc::new(RefCell::new(base));
-        let cb_data: Box<Rc<RefCell<dyn ReadAndSeek>>> = Box::new(base.clone());
-        let job = unsafe { raw::rs_patch_begin(patch_copy_cb, mem::transmute(&*cb_data)) };
-        assert!(!job.is_null());
-        Ok(Patch {
-            driver: JobDriver::new(delta, Job(job)),
-            base,
-            raw: cb_data,
-        })
+    /// If it is zero, the default block length of librsync (2048 bytes) is used instead, and
+    /// recorded in this builder.
+    pub fn block_len(mut self, block_len: usize) -> Self {
+        self.block_len = default_block_len(block_len);
+        self
     }
 
-    /// Unwraps this stream and returns the underlying streams.
-    pub fn into_inner(self) -> (B, D) {
-        // drop the secondary Rc before unwrapping the other
-        {
-            let _drop = self.raw;
-        }
-        let base = match Rc::try_unwrap(self.base) {
-            Ok(base) => base,
-            _ => unreachable!(),
-        };
-        (base.into_inner(), self.driver.into_inner())
+    /// Sets the size of strong signatures, in bytes.
+    ///
+    /// If it is zero, the full length of the strong signatures is used.
+    pub fn strong_len(mut self, strong_len: usize) -> Self {
+        self.strong_len = strong_len;
+        self
     }
-}
 
-impl<'a, B, D: BufRead> Read for Patch<'a, B, D> {
-    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
-        self.driver.read(buf)
+    /// Sets the signature format.
+    pub fn sig_type(mut self, sig_type: SignatureType) -> Self {
+        self.sig_type = sig_type;
+        self
     }
-}
 
-unsafe impl<'a, B: 'a, D> Send for Patch<'a, B, D>
-where
-    B: Send,
-    D: Send,
-{
-}
+    /// Sets the size of the buffer used to read the input, in bytes.
+    ///
+    /// This is ignored by `build_buf_read`, since the given input is already buffered.
+    pub fn in_buf_len(mut self, in_buf_len: usize) -> Self {
+        self.in_buf_len = in_buf_len;
+        self
+    }
 
-impl error::Error for Error {}
+    /// Sets the size of the buffer librsync writes the signature to, in bytes.
+    pub fn out_buf_len(mut self, out_buf_len: usize) -> Self {
+        self.out_buf_len = out_buf_len;
+        self
+    }
 
-impl Display for Error {
-    fn fmt(&self, fmt: &mut Formatter) -> fmt::Result {
-        match *self {
-            Error::Io(ref e) => write!(fmt, "{}", e),
-            Error::Mem => write!(fmt, "out of memory"),
-            Error::BadMagic => write!(fmt, "bad magic number given"),
-            Error::Unimplemented => write!(fmt, "unimplemented feature"),
-            Error::Internal => write!(fmt, "internal error"),
-            Error::Unknown(n) => write!(fmt, "unknown error {} from native library", n),
+    /// Builds a signature stream over the given input.
+    pub fn build<R: Read>(self, input: R) -> Result<Signature<BufReader<R>>> {
+        if self.in_buf_len == 0 {
+            return Err(invalid_param("in_buf_len", "empty input buffer"));
         }
+        self.build_buf_read(BufReader::with_capacity(self.in_buf_len, input))
     }
-}
 
-impl From<io::Error> for Error {
-    fn from(err: io::Error) -> Error {
-        Error::Io(err)
+    /// Builds a signature stream over the given `BufRead` input.
+    pub fn build_buf_read<R: BufRead>(self, input: R) -> Result<Signature<R>> {
+        self.build_in(input, Vec::new())
     }
-}
 
-impl From<raw::rs_result> for Error {
-    fn from(err: raw::rs_result) -> Error {
-        match err {
-            raw::RS_BLOCKED => io_err(io::ErrorKind::WouldBlock, "blocked waiting for more data"),
-            raw::RS_IO_ERROR => io_err(io::ErrorKind::Other, "unknown IO error from librsync"),
-            raw::RS_MEM_ERROR => Error::Mem,
-            raw::RS_INPUT_ENDED => {
-                io_err(io::ErrorKind::UnexpectedEof, "unexpected end of input file")
-            }
-            raw::RS_BAD_MAGIC => Error::BadMagic,
-            raw::RS_UNIMPLEMENTED => Error::Unimplemented,
-            raw::RS_CORRUPT => io_err(io::ErrorKind::InvalidData, "unbelievable value in stream"),
-            raw::RS_INTERNAL_ERROR => Error::Internal,
-            raw::RS_PARAM_ERROR => io_err(io::ErrorKind::InvalidInput, "bad parameter"),
-            n => Error::Unknown(n),
+    // Builds a signature stream writing the output into the given buffer, once resized.
+    fn build_in<R: BufRead>(self, input: R, mut out_buf: Vec<u8>) -> Result<Signature<R>> {
+        if self.out_buf_len == 0 {
+            return Err(invalid_param("out_buf_len", "empty output buffer"));
         }
+        let job = sig_begin(self.block_len, self.strong_len, self.sig_type)?;
+        out_buf.resize(self.out_buf_len, 0);
+        Ok(Signature {
+            driver: JobDriver::with_buf(input, job, out_buf),
+            progress: None,
+            #[cfg(feature = "output-trace")]
+            trace: None,
+        })
     }
 }
 
-impl SignatureType {
-    fn as_raw(self) -> raw::rs_magic_number {
-        match self {
-            SignatureType::MD4 => raw::RS_MD4_SIG_MAGIC,
-            SignatureType::Blake2 => raw::RS_BLAKE2_SIG_MAGIC,
-        }
+impl Default for SignatureBuilder {
+    fn default() -> Self {
+        Self::new()
     }
 }
 
-impl Drop for Sumset {
-    fn drop(&mut self) {
-        unsafe {
-            if !self.0.is_null() {
-                raw::rs_free_sumset(self.0);
-            }
-        }
-    }
+/// The smallest block length returned by `block_len_for_granularity` and
+/// `block_len_for_resolution`, in bytes.
+///
+/// Shorter blocks make the signature, and the work to compute a delta, grow a lot, for little
+/// gain in the delta size.
+pub const MIN_BLOCK_LEN: usize = 256;
+
+// the largest block length which can be stored in a signature header
+const MAX_BLOCK_LEN: u64 = 1 << 31;
+
+/// Returns a block length splitting a file in about `target_blocks` blocks.
+///
+/// The block length is rounded up to a power of two, so that the signature of a file of
+/// `file_len` bytes has at most `target_blocks` blocks, unless the block length is raised to
+/// `MIN_BLOCK_LEN`. A zero `target_blocks` is taken as one block. The result can be given to
+/// `Signature::with_options` or to `SignatureBuilder::block_len`.
+pub fn block_len_for_granularity(file_len: u64, target_blocks: usize) -> usize {
+    let target_blocks = cmp::max(target_blocks, 1) as u64;
+    let block_len = div_ceil(file_len, target_blocks).min(MAX_BLOCK_LEN);
+    cmp::max(block_len.next_power_of_two() as usize, MIN_BLOCK_LEN)
 }
 
-impl Deref for Sumset {
-    type Target = *mut raw::rs_signature_t;
-    fn deref(&self) -> &Self::Target {
-        &self.0
+/// Returns a block length able to detect changes down to about `resolution_bytes` bytes.
+///
+/// A delta can only reuse whole blocks of the basis file, so a change invalidates at least one
+/// block, and a region of the new file is only matched if it spans a whole block. The block
+/// length is the largest power of two not greater than `resolution_bytes`, but not shorter than
+/// `MIN_BLOCK_LEN`.
+pub fn block_len_for_resolution(resolution_bytes: usize) -> usize {
+    let resolution = (resolution_bytes as u64).min(MAX_BLOCK_LEN);
+    if resolution <= MIN_BLOCK_LEN as u64 {
+        return MIN_BLOCK_LEN;
     }
+    // the highest bit set in the resolution
+    (1u64 << (63 - resolution.leading_zeros())) as usize
 }
 
-unsafe impl Send for Sumset {}
-
-extern "C" fn patch_copy_cb(
-    opaque: *mut libc::c_void,
-    pos: raw::rs_long_t,
-    len: *mut libc::size_t,
-    buf: *mut *mut libc::c_void,
-) -> raw::rs_result {
-    let mut input: RefMut<dyn ReadAndSeek> = unsafe {
-        let h: *mut Rc<RefCell<dyn ReadAndSeek>> = mem::transmute(opaque);
-        (*h).borrow_mut()
-    };
-    let output = unsafe {
-        let buf: *mut u8 = mem::transmute(*buf);
-        slice::from_raw_parts_mut(buf, *len)
-    };
-    try_or_rs_error!(input.seek(io::SeekFrom::Start(pos as u64)));
-    try_or_rs_error!(input.read(output));
-    raw::RS_DONE
+impl<W: Write> SignatureWriter<W> {
+    /// Creates a new signature writer with default parameters.
+    ///
+    /// This constructor takes the output stream to which the signature is written. Default
+    /// options are the same as `Signature::new`.
+    pub fn new(output: W) -> Result<Self> {
+        Self::with_options(output, raw::RS_DEFAULT_BLOCK_LEN, 0, SignatureType::Blake2)
+    }
+
+    /// Creates a new signature writer by specifying custom parameters.
+    ///
+    /// See `Signature::with_options` for details on the parameters.
+    pub fn with_options(
+        output: W,
+        block_len: usize,
+        strong_len: usize,
+        sig_magic: SignatureType,
+    ) -> Result<Self> {
+        let job = sig_begin(block_len, strong_len, sig_magic)?;
+        Ok(SignatureWriter {
+            writer: JobWriter::new(output, job),
+        })
+    }
+
+    /// Returns the statistics collected so far by the underlying job.
+    pub fn stats(&self) -> Stats {
+        self.writer.stats()
+    }
+
+    /// Signals the end of the input, and completes the signature.
+    ///
+    /// The remaining part of the signature is written out, and the output stream is returned.
+    /// This method consumes the writer, so no more input can be written after it.
+    pub fn finish(self) -> Result<W> {
+        Ok(self.writer.finish()?)
+    }
+}
+
+impl<W: Write> Write for SignatureWriter<W> {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        self.writer.write(buf)
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        self.writer.flush()
+    }
+}
+
+impl<R: Read> SignatureTee<R> {
+    /// Creates a new signature tee with default parameters.
+    ///
+    /// See `Signature::new` for the default parameters.
+    pub fn new(input: R) -> Result<Self> {
+        Self::with_options(input, raw::RS_DEFAULT_BLOCK_LEN, 0, SignatureType::Blake2)
+    }
+
+    /// Creates a new signature tee by specifying custom parameters.
+    ///
+    /// See `Signature::with_options` for details on the parameters.
+    pub fn with_options(
+        input: R,
+        block_len: usize,
+        strong_len: usize,
+        sig_magic: SignatureType,
+    ) -> Result<Self> {
+        Ok(SignatureTee {
+            input,
+            sig: SignatureWriter::with_options(Vec::new(), block_len, strong_len, sig_magic)?,
+        })
+    }
+
+    /// Completes the signature, and returns it along with the underlying input stream.
+    ///
+    /// The signature covers only the bytes read so far, so the stream should be read to its
+    /// end before calling this method, to get the signature of the whole input.
+    pub fn finish(self) -> Result<(R, Vec<u8>)> {
+        let sig = self.sig.finish()?;
+        Ok((self.input, sig))
+    }
+}
+
+impl<R: Read> Read for SignatureTee<R> {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        let read = self.input.read(buf)?;
+        self.sig.write_all(&buf[..read])?;
+        Ok(read)
+    }
+}
+
+impl<R: Read> Delta<BufReader<R>> {
+    /// Creates a new delta stream.
+    ///
+    /// This constructor takes two `Read` streams for the new file (`new` parameter) and for the
+    /// signatures of the base file (`base_sig` parameter). It produces a delta stream from which
+    /// read the resulting delta file.
+    ///
+    /// The signature is fully loaded in memory by this constructor. Since `base_sig` is only
+    /// borrowed, the caller gets it back positioned at its end, and can reuse it afterwards.
+    pub fn new<S: Read + ?Sized>(new: R, base_sig: &mut S) -> Result<Self> {
+        Self::with_buf_read(BufReader::new(new), base_sig)
+    }
+
+    /// Computes the whole delta of a new file, along with its statistics.
+    ///
+    /// The delta between the `new` stream and the signature of the base file is computed like
+    /// with `new`, and returned in a `Vec`, together with the statistics of the job, once it is
+    /// complete.
+    pub fn compute<S: Read>(new: R, mut base_sig: S) -> Result<(Vec<u8>, Stats)> {
+        let mut delta = Self::new(new, &mut base_sig)?;
+        let mut out = Vec::new();
+        delta.read_to_end(&mut out)?;
+        Ok((out, delta.stats()))
+    }
+
+    /// Creates a new delta stream which must be completed before a deadline.
+    ///
+    /// This constructor behaves like `new`, but once the `deadline` is passed, reading from the
+    /// stream fails with an `ErrorKind::TimedOut` error. The deadline is checked each time
+    /// librsync is given a new buffer to work on, so the overhead is negligible, and the
+    /// computation is never interrupted in an inconsistent state.
+    pub fn new_with_deadline<S: Read + ?Sized>(
+        new: R,
+        base_sig: &mut S,
+        deadline: Instant,
+    ) -> Result<Self> {
+        let mut delta = Self::new(new, base_sig)?;
+        delta.driver.set_deadline(deadline);
+        Ok(delta)
+    }
+
+    /// Creates a new delta stream, bounding the memory used by the loaded signature.
+    ///
+    /// This constructor behaves like `new`, but returns `Error::Mem` if loading the signature
+    /// would take more than `mem_limit` bytes, so that an oversized signature coming from an
+    /// untrusted party cannot exhaust the memory. Every block of the signature is counted as
+    /// its weak and strong checksums (`4 + strong_len` bytes), and the loading stops as soon as
+    /// the limit is exceeded.
+    ///
+    /// librsync (up to 2.3) has no way to bound the memory of `rs_loadsig_begin`, so the limit is
+    /// enforced by this crate, by counting the blocks before they are given to librsync. The
+    /// hash table built afterwards is not accounted for.
+    pub fn new_with_limit<S: Read + ?Sized>(
+        new: R,
+        base_sig: &mut S,
+        mem_limit: usize,
+    ) -> Result<Self> {
+        let mut header = [0; SignatureInfo::HEADER_LEN];
+        base_sig.read_exact(&mut header)?;
+        let info = SignatureInfo::parse(&header)?;
+        let entry_len = 4 + info.strong_len as u64;
+        // one byte past the allowed blocks tells an oversized signature from a fitting one
+        let max_len = (mem_limit as u64 / entry_len) * entry_len + 1;
+        let mut entries = (&mut *base_sig).take(max_len);
+        let loaded = load_signature(&mut (&header[..]).chain(&mut entries));
+        if entries.limit() == 0 {
+            return Err(Error::Mem);
+        }
+        Delta::with_sumset(BufReader::new(new), loaded?)
+    }
+
+    /// Creates a new delta stream which never copies from beyond an offset of the base file.
+    ///
+    /// This constructor behaves like `new`, but the resulting delta patches correctly against the
+    /// base file truncated at `max_base_offset` bytes, for example when only a prefix of the base
+    /// has been downloaded. The parts of the new file which would be copied from beyond that
+    /// offset are stored as literal data instead.
+    ///
+    /// The copies are avoided by loading only the blocks of the signature which end within
+    /// `max_base_offset`, so that librsync never matches the other ones. Since the blocks are
+    /// stored in order, the offsets of the loaded ones are unchanged. The rest of the signature is
+    /// read and discarded. A signature header with a zero block length is reported as
+    /// `Error::Corrupt`.
+    pub fn new_bounded_base<S: Read + ?Sized>(
+        new: R,
+        base_sig: &mut S,
+        max_base_offset: u64,
+    ) -> Result<Self> {
+        let mut header = [0; SignatureInfo::HEADER_LEN];
+        base_sig.read_exact(&mut header)?;
+        let info = SignatureInfo::parse(&header)?;
+        if info.block_len == 0 {
+            return Err(Error::Corrupt);
+        }
+        let blocks = max_base_offset / info.block_len as u64;
+        let mut entries = (&mut *base_sig).take(blocks * (4 + info.strong_len as u64));
+        let sumset = load_signature(&mut (&header[..]).chain(&mut entries))?;
+        io::copy(base_sig, &mut io::sink())?;
+        Delta::with_sumset(BufReader::new(new), sumset)
+    }
+
+    /// Creates a new delta stream from a signature loaded by librsync.
+    ///
+    /// This constructor takes the new file stream (`new` parameter), and a signature already
+    /// loaded in memory through the librsync C API (`sig` parameter), for example with
+    /// `rs_loadsig_begin`. The hash table of the signature is built by this constructor.
+    ///
+    /// # Safety
+    ///
+    /// The `sig` parameter must be a valid signature, allocated by the same librsync linked by
+    /// this crate, and its hash table must not have been built yet. The ownership of the signature
+    /// is transferred to the delta, which frees it when dropped, even in case of errors: the
+    /// caller must not use or free the signature after this call.
+    pub unsafe fn from_raw_signature(new: R, sig: *mut raw::rs_signature_t) -> Result<Self> {
+        logfwd::init();
+
+        let sumset = build_hash_table(Sumset(sig))?;
+        Delta::with_sumset(BufReader::new(new), sumset)
+    }
+
+    /// Resumes an interrupted delta computation from a checkpoint.
+    ///
+    /// This constructor takes the whole new file (`new` parameter) and the signature of the base
+    /// file (`base_sig` parameter), like `new`, and a checkpoint taken by a previous delta stream
+    /// with `Delta::checkpoint`. The first `checkpoint.input_offset` bytes of the new file are
+    /// skipped, and the resulting stream produces the rest of the delta, without the header. Once
+    /// the previous output is truncated to `checkpoint.output_len` bytes, appending this stream
+    /// to it produces a complete delta, which patches to the same result of an uninterrupted one.
+    ///
+    /// librsync internal state cannot be saved, so the resumed delta restarts matching blocks from
+    /// the checkpoint. This keeps the delta valid, but it may differ from the uninterrupted one.
+    pub fn resume<S: Read + ?Sized>(
+        mut new: R,
+        base_sig: &mut S,
+        checkpoint: Checkpoint,
+    ) -> Result<Self> {
+        let skipped = io::copy(
+            &mut (&mut new).take(checkpoint.input_offset),
+            &mut io::sink(),
+        )?;
+        if skipped < checkpoint.input_offset {
+            return Err(io_err(
+                io::ErrorKind::UnexpectedEof,
+                "new file is shorter than the checkpoint",
+            ));
+        }
+        let mut delta = Self::new(new, base_sig)?;
+        // the previous output already contains the header
+        delta.driver.read_exact(&mut [0; 4])?;
+        delta.tracker = CommandTracker::resume(checkpoint.output_len, checkpoint.input_offset);
+        Ok(delta)
+    }
+
+    /// Creates a new delta stream, rejecting weak signatures.
+    ///
+    /// This constructor behaves like `new`, but returns `Error::WeakChecksum` if the signature
+    /// uses MD4 strong checksums, with any rolling checksum. It allows to refuse legacy
+    /// signatures coming from untrusted parties, since MD4 is vulnerable to collisions.
+    pub fn new_strict<S: Read + ?Sized>(new: R, base_sig: &mut S) -> Result<Self> {
+        let mut header = [0; SignatureInfo::HEADER_LEN];
+        base_sig.read_exact(&mut header)?;
+        if SignatureInfo::parse(&header)?.sig_type.digest() == DigestKind::MD4 {
+            return Err(Error::WeakChecksum);
+        }
+        let sumset = load_signature(&mut (&header[..]).chain(base_sig))?;
+        Delta::with_sumset(BufReader::new(new), sumset)
+    }
+}
+
+impl<R: BufRead> Delta<R> {
+    /// Creates a new delta stream by using a `BufRead` as new file.
+    ///
+    /// This constructor specializes the `new` constructor by taking a `BufRead` instance as
+    /// `new` parameter. Prefer this constructor if you already have a `BufRead` as input stream,
+    /// since it avoids wrapping the input stream into another `BufRead` instance. See `new`
+    /// constructor for more details on the parameters.
+    pub fn with_buf_read<S: Read + ?Sized>(new: R, base_sig: &mut S) -> Result<Self> {
+        let sumset = load_signature(base_sig)?;
+        Self::with_sumset(new, sumset)
+    }
+
+    fn with_sumset(new: R, sumset: Sumset) -> Result<Self> {
+        let job = unsafe { raw::rs_delta_begin(*sumset) };
+        if job.is_null() {
+            return Err(io_err(
+                io::ErrorKind::InvalidData,
+                "invalid signature given",
+            ));
+        }
+        Ok(Delta {
+            driver: JobDriver::new(new, Job::new(job)),
+            _sumset: sumset,
+            tracker: CommandTracker::new(),
+            progress: None,
+        })
+    }
+
+    /// Returns the statistics collected so far by the underlying job.
+    pub fn stats(&self) -> Stats {
+        self.driver.stats()
+    }
+
+    /// Installs a collector of the events of the underlying job.
+    ///
+    /// The collector replaces any previously installed one, and receives the events of the job
+    /// from now on. See `Metrics` for the events produced.
+    pub fn with_metrics<M: Metrics + 'static>(mut self, metrics: M) -> Self {
+        self.driver.set_metrics(Box::new(metrics), OpKind::Delta);
+        self
+    }
+
+    /// Installs a callback reporting the fraction of the new file processed.
+    ///
+    /// The length of the new file is given by the caller as `new_len`, since the stream may not
+    /// know it. After a read takes more input from the new file, the callback is called with the
+    /// bytes consumed so far divided by `new_len`, clamped between 0 and 1, so it fires at most
+    /// once per buffer of input, and never with a decreasing fraction. Once the delta is
+    /// complete, 1 is reported, even if `new_len` was underestimated.
+    pub fn with_fraction_progress<F>(mut self, new_len: u64, f: F) -> Self
+    where
+        F: FnMut(f64) + Send + 'static,
+    {
+        self.progress = Some(FractionProgress {
+            new_len,
+            last: 0.0,
+            callback: Box::new(f),
+        });
+        self
+    }
+
+    /// Returns the number of bytes of the new file processed so far.
+    ///
+    /// The bytes are counted as soon as librsync takes them from the new file. When reading
+    /// from this stream fails, it reports how far the new file was processed before the error,
+    /// whether the new file failed to be read, or librsync failed to process it.
+    pub fn input_consumed(&self) -> u64 {
+        self.driver.in_bytes()
+    }
+
+    /// Returns the version of the delta format produced, as written in its magic number.
+    ///
+    /// The magic number of a delta is made of `rs`, the format version and the delta type, so
+    /// the version is its third byte. See `whole::patch_checked` to check it before patching.
+    pub fn format_version(&self) -> u8 {
+        command::DELTA_MAGIC[2]
+    }
+
+    /// Returns whether the underlying job has produced all its output.
+    ///
+    /// This reports whether librsync is done producing the output, not whether the output has
+    /// been completely read: when it becomes `true`, some bytes may still be pending (see
+    /// `pending_output_len`), and the stream reaches its end once those are read.
+    pub fn is_done(&self) -> bool {
+        self.driver.is_done()
+    }
+
+    /// Returns the state of the underlying job, after the last read.
+    ///
+    /// This complements `is_done`, by telling a job waiting for more input from a failed one.
+    pub fn state(&self) -> JobState {
+        self.driver.state()
+    }
+
+    /// Returns the number of bytes already produced, but not read yet.
+    pub fn pending_output_len(&self) -> usize {
+        self.driver.pending_output_len()
+    }
+
+    /// Writes all the remaining output to the given stream.
+    ///
+    /// This works like `io::copy`, but writes directly from the internal output buffer, without
+    /// an intermediate copy. Any byte already read from this stream is not written again. In
+    /// case of success, the number of bytes written is returned.
+    pub fn copy_to<W: Write + ?Sized>(&mut self, out: &mut W) -> Result<u64> {
+        let mut out = TrackingWriter {
+            inner: out,
+            tracker: &mut self.tracker,
+        };
+        let written = self.driver.copy_to(&mut out);
+        self.report_progress();
+        Ok(written?)
+    }
+
+    /// Returns a checkpoint, from which the delta computation can be resumed.
+    ///
+    /// The checkpoint refers to the end of the last complete command among the ones read from
+    /// this stream, so it accounts only for the delta already read, which should be kept up to
+    /// the checkpoint `output_len`. See `resume` for details.
+    pub fn checkpoint(&self) -> Checkpoint {
+        let (output_len, input_offset) = self.tracker.boundary();
+        Checkpoint {
+            input_offset,
+            output_len,
+        }
+    }
+
+    /// Unwraps this stream, returning the underlying new file stream.
+    pub fn into_inner(self) -> R {
+        self.driver.into_inner()
+    }
+}
+
+impl<R: BufRead> Delta<R> {
+    fn report_progress(&mut self) {
+        let progress = match self.progress {
+            Some(ref mut progress) => progress,
+            None => return,
+        };
+        let fraction = if self.driver.is_done() || progress.new_len == 0 {
+            1.0
+        } else {
+            (self.driver.in_bytes() as f64 / progress.new_len as f64).min(1.0)
+        };
+        if fraction > progress.last {
+            progress.last = fraction;
+            (progress.callback)(fraction);
+        }
+    }
+}
+
+impl<R: BufRead> Read for Delta<R> {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        let read = self.driver.read(buf);
+        self.report_progress();
+        let read = read?;
+        self.tracker.update(&buf[..read]);
+        Ok(read)
+    }
+}
+
+impl<'a, B: Read + Seek + 'a, D: Read> Patch<'a, B, BufReader<D>> {
+    /// Creates a new patch stream.
+    ///
+    /// This constructor takes a `Read + Seek` stream for the basis file (`base` parameter), and a
+    /// `Read` stream for the delta file (`delta` parameter). It produces a stream from which read
+    /// the resulting patched file.
+    ///
+    /// The delta is consumed incrementally, as the output is read, so it can come from a
+    /// non-blocking socket: an `ErrorKind::WouldBlock` error reading the delta is returned as is,
+    /// before librsync is given any data, and the read can be retried once more data arrives.
+    pub fn new(base: B, delta: D) -> Result<Self> {
+        Self::with_buf_read(base, BufReader::new(delta))
+    }
+
+    /// Creates a new patch stream, retrying the reads of the basis file on transient errors.
+    ///
+    /// This constructor behaves like `new`, but when reading from the basis file fails with an
+    /// `ErrorKind::Interrupted` or `ErrorKind::WouldBlock` error, the seek and the read are
+    /// retried up to `retries` times, before giving up. This is useful for flaky bases, like the
+    /// ones backed by a network. Other errors make the patch fail immediately.
+    pub fn new_with_retry(base: B, delta: D, retries: usize) -> Result<Self> {
+        Self::with_retries(base, BufReader::new(delta), retries)
+    }
+
+    /// Creates a new patch stream, limiting the size of the patched file.
+    ///
+    /// This constructor behaves like `new`, but the resulting stream produces at most
+    /// `max_bytes` bytes. If the delta would expand beyond that, the job is stopped and reading
+    /// fails with an `ErrorKind::InvalidData` error. Use this to guard against malicious deltas,
+    /// which can expand to an enormous output by repeating copies of a large region of the
+    /// basis file.
+    pub fn with_max_output(base: B, delta: D, max_bytes: u64) -> Result<Self> {
+        let mut patch = Self::new(base, delta)?;
+        patch.driver.set_max_output(max_bytes);
+        Ok(patch)
+    }
+
+    /// Creates a new patch stream, computing the signature of the patched file in the same pass.
+    ///
+    /// The patch stream is wrapped in a `SignatureTee`, which yields the patched file and signs
+    /// it on the side, with the given signature parameters (see `Signature::with_options`). Once
+    /// the stream is read to its end, `SignatureTee::finish` returns the signature of the patched
+    /// file, without reading it again.
+    pub fn with_output_signature(
+        base: B,
+        delta: D,
+        block_len: usize,
+        strong_len: usize,
+        sig_magic: SignatureType,
+    ) -> Result<SignatureTee<Self>> {
+        let patch = Self::new(base, delta)?;
+        SignatureTee::with_options(patch, block_len, strong_len, sig_magic)
+    }
+}
+
+impl<'a, B, F, D> Patch<'a, LazyBase<B, F>, BufReader<D>>
+where
+    B: Read + Seek + 'a,
+    F: FnOnce() -> io::Result<B> + 'a,
+    D: Read,
+{
+    /// Creates a new patch stream, opening the basis file only if needed.
+    ///
+    /// This constructor behaves like `new`, but instead of the basis file, it takes a function
+    /// which opens it (`open_base` parameter). The function is called the first time the delta
+    /// copies data from the basis file, so, if the delta contains only literal data, the basis
+    /// file is never opened.
+    pub fn new_lazy(open_base: F, delta: D) -> Result<Self> {
+        Self::new(LazyBase::new(open_base), delta)
+    }
+}
+
+impl<'a, F, D> Patch<'a, ChunkedBase<F>, BufReader<D>>
+where
+    F: FnMut(u64) -> io::Result<Vec<u8>> + 'a,
+    D: Read,
+{
+    /// Creates a new patch stream, over a basis file stored in chunks.
+    ///
+    /// This constructor behaves like `new`, but instead of a seekable basis file, it takes a
+    /// function returning the chunk of the basis file with the given index (`fetch_chunk`
+    /// parameter), where every chunk is `chunk_size` bytes long. See `ChunkedBase` for details.
+    pub fn from_chunk_fn(delta: D, fetch_chunk: F, chunk_size: u64) -> Result<Self> {
+        Self::new(ChunkedBase::new(fetch_chunk, chunk_size), delta)
+    }
 }
 
-fn io_err<E>(kind: io::ErrorKind, e: E) -> Error
-where
-    E: Into<Box<dyn error::Error + Send + Sync>>,
-{
-    Error::Io(io::Error::new(kind, e))
-}
+impl<'a, B: Read + Seek + 'a, D: BufRead> Patch<'a, B, D> {
+    /// Creates a new patch stream by using a `BufRead` as delta stream.
+    ///
+    /// This constructor specializes the `new` constructor by taking a `BufRead` instance as
+    /// `delta` parameter. Prefer this constructor if you already have a `BufRead` as input
+    /// stream, since it avoids wrapping the input stream into another `BufRead` instance. See
+    /// `new` constructor for more details on the parameters.
+    pub fn with_buf_read(base: B, delta: D) -> Result<Self> {
+        Self::with_retries(base, delta, 0)
+    }
+
+    fn with_retries(base: B, delta: D, retries: usize) -> Result<Self> {
+        logfwd::init();
+
+        let base = Rc::new(RefCell::new(PositionedBase::new(base)));
+        let cb_data = Box::new(CopySource {
+            base: base.clone(),
+            retries,
+            #[cfg(feature = "copy-hook")]
+            on_copy: RefCell::new(None),
+            copies: RefCell::new(None),
+        });
+        let job = unsafe { raw::rs_patch_begin(patch_copy_cb, mem::transmute(&*cb_data)) };
+        if job.is_null() {
+            return Err(Error::Mem);
+        }
+        Ok(Patch {
+            driver: JobDriver::new(delta, Job::new(job)),
+            base,
+            raw: cb_data,
+        })
+    }
+
+    /// Returns the statistics collected so far by the underlying job.
+    pub fn stats(&self) -> Stats {
+        self.driver.stats()
+    }
+
+    /// Installs a collector of the events of the underlying job.
+    ///
+    /// The collector replaces any previously installed one, and receives the events of the job
+    /// from now on. See `Metrics` for the events produced.
+    pub fn with_metrics<M: Metrics + 'static>(mut self, metrics: M) -> Self {
+        self.driver.set_metrics(Box::new(metrics), OpKind::Patch);
+        self
+    }
+
+    /// Sets a function called before every read of the basis file.
+    ///
+    /// The function is given the offset and the length requested by librsync, before the basis
+    /// file is read, so it can be used to trace the regions a misbehaving delta copies. Any
+    /// previously set function is replaced. Available with the `copy-hook` feature only.
+    #[cfg(feature = "copy-hook")]
+    pub fn on_copy<F: FnMut(u64, usize) + 'a>(&mut self, f: F) {
+        *self.raw.on_copy.borrow_mut() = Some(Box::new(f));
+    }
+
+    /// Starts recording the regions of the basis file copied to the output.
+    ///
+    /// From now on, every region read from the basis file is recorded, and the list can be
+    /// retrieved with `into_parts`, for example to verify afterwards that the basis file has not
+    /// changed. The record grows with the number of copies, so it is disabled by default.
+    pub fn record_copies(&mut self) {
+        let mut copies = self.raw.copies.borrow_mut();
+        if copies.is_none() {
+            *copies = Some(Vec::new());
+        }
+    }
+
+    /// Returns whether the underlying job has produced all its output.
+    ///
+    /// This reports whether librsync is done producing the output, not whether the output has
+    /// been completely read: when it becomes `true`, some bytes may still be pending (see
+    /// `pending_output_len`), and the stream reaches its end once those are read.
+    pub fn is_done(&self) -> bool {
+        self.driver.is_done()
+    }
+
+    /// Returns the state of the underlying job, after the last read.
+    ///
+    /// This complements `is_done`, by telling a job waiting for more input from a failed one.
+    pub fn state(&self) -> JobState {
+        self.driver.state()
+    }
+
+    /// Returns the number of bytes already produced, but not read yet.
+    pub fn pending_output_len(&self) -> usize {
+        self.driver.pending_output_len()
+    }
+
+    /// Writes all the remaining output to the given stream.
+    ///
+    /// This works like `io::copy`, but writes directly from the internal output buffer, without
+    /// an intermediate copy. Any byte already read from this stream is not written again. In
+    /// case of success, the number of bytes written is returned.
+    pub fn copy_to<W: Write + ?Sized>(&mut self, out: &mut W) -> Result<u64> {
+        let written = self.driver.copy_to(out)?;
+        Ok(written)
+    }
+
+    /// Unwraps this stream, returning the basis file and the copies recorded.
+    ///
+    /// The copies are the `Command::Copy` regions read from the basis file since
+    /// `record_copies` was called, in order, or none if it was never called. librsync may read
+    /// a copy command in several pieces, so that contiguous reads are recorded as a single
+    /// region. Since the commands of a delta can be contiguous too, the regions may span more
+    /// than one of them.
+    pub fn into_parts(self) -> (B, Vec<Command>) {
+        let copies = self.raw.copies.borrow_mut().take().unwrap_or_default();
+        (self.into_inner().0, copies)
+    }
+
+    /// Unwraps this stream and returns the underlying streams.
+    pub fn into_inner(self) -> (B, D) {
+        // drop the secondary Rc before unwrapping the other
+        {
+            let _drop = self.raw;
+        }
+        let base = match Rc::try_unwrap(self.base) {
+            Ok(base) => base,
+            _ => unreachable!(),
+        };
+        (base.into_inner().into_inner(), self.driver.into_inner())
+    }
+}
+
+impl<'a, B, D: BufRead> Read for Patch<'a, B, D> {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        self.driver.read(buf)
+    }
+}
+
+unsafe impl<'a, B: 'a, D> Send for Patch<'a, B, D>
+where
+    B: Send,
+    D: Send,
+{
+}
+
+impl error::Error for Error {}
+
+impl Error {
+    /// Returns the librsync result code corresponding to this error, if any.
+    ///
+    /// This is the code reported by librsync for the errors coming from it, and the code with
+    /// the same meaning for the errors detected by this crate, like `Corrupt`.
+    pub fn code(&self) -> Option<i32> {
+        match *self {
+            Error::Io(ref e) => e
+                .get_ref()
+                .and_then(|inner| inner.downcast_ref::<NativeError>())
+                .map(|native| native.code),
+            Error::Mem => Some(raw::RS_MEM_ERROR),
+            Error::BadMagic => Some(raw::RS_BAD_MAGIC),
+            Error::WeakChecksum => None,
+            Error::Corrupt => Some(raw::RS_CORRUPT),
+            Error::Unimplemented => Some(raw::RS_UNIMPLEMENTED),
+            Error::Internal => Some(raw::RS_INTERNAL_ERROR),
+            Error::InvalidParameter { .. } => Some(raw::RS_PARAM_ERROR),
+            Error::Unknown(n) => Some(n),
+        }
+    }
+
+    /// Returns the classification of this error.
+    ///
+    /// IO errors wrapping a librsync syntax error are classified as `ErrorKind::Syntax`, all
+    /// the other ones as `ErrorKind::Io`.
+    pub fn kind(&self) -> ErrorKind {
+        match *self {
+            Error::Io(_) if self.code() == Some(raw::RS_SYNTAX_ERROR) => ErrorKind::Syntax,
+            Error::Io(_) => ErrorKind::Io,
+            Error::Mem => ErrorKind::Mem,
+            Error::BadMagic => ErrorKind::BadMagic,
+            Error::WeakChecksum => ErrorKind::WeakChecksum,
+            Error::Corrupt => ErrorKind::Corrupt,
+            Error::Unimplemented => ErrorKind::Unimplemented,
+            Error::Internal => ErrorKind::Internal,
+            Error::InvalidParameter { .. } => ErrorKind::InvalidParameter,
+            Error::Unknown(_) => ErrorKind::Unknown,
+        }
+    }
+}
+
+impl Display for Error {
+    // The librsync code is only shown when it is the one actually returned by librsync, which is
+    // kept by IO and unknown errors. The other variants are also reported by this crate, so their
+    // code, as given by `code`, may not come from librsync.
+    fn fmt(&self, fmt: &mut Formatter) -> fmt::Result {
+        let msg = match *self {
+            Error::Io(ref e) => return write!(fmt, "{}", e),
+            Error::Mem => "out of memory",
+            Error::BadMagic => "bad magic number given",
+            Error::WeakChecksum => "weak checksum signature rejected",
+            Error::Corrupt => "unbelievable value in stream, input is corrupt",
+            Error::Unimplemented => "unimplemented feature",
+            Error::Internal => "internal error",
+            Error::InvalidParameter { name, ref detail } => {
+                return write!(fmt, "invalid parameter `{}`: {}", name, detail);
+            }
+            Error::Unknown(code) => {
+                return write!(
+                    fmt,
+                    "unknown error from native library (librsync code {})",
+                    code
+                );
+            }
+        };
+        fmt.write_str(msg)
+    }
+}
+
+// An error reported by librsync as an IO error, keeping its result code.
+#[derive(Debug)]
+struct NativeError {
+    code: raw::rs_result,
+    msg: &'static str,
+}
+
+impl error::Error for NativeError {}
+
+impl Display for NativeError {
+    fn fmt(&self, fmt: &mut Formatter) -> fmt::Result {
+        write!(fmt, "{} (librsync code {})", self.msg, self.code)
+    }
+}
+
+impl From<io::Error> for Error {
+    fn from(err: io::Error) -> Error {
+        // the streaming types report librsync errors wrapped into IO errors, unwrap them
+        match err.get_ref().map(|inner| inner.is::<Error>()) {
+            Some(true) => *err.into_inner().unwrap().downcast::<Error>().unwrap(),
+            _ => Error::Io(err),
+        }
+    }
+}
+
+impl From<raw::rs_result> for Error {
+    fn from(err: raw::rs_result) -> Error {
+        let (kind, msg) = match err {
+            raw::RS_BLOCKED => (io::ErrorKind::WouldBlock, "blocked waiting for more data"),
+            raw::RS_IO_ERROR => (io::ErrorKind::Other, "unknown IO error from librsync"),
+            raw::RS_SYNTAX_ERROR => (io::ErrorKind::InvalidInput, "syntax error"),
+            raw::RS_MEM_ERROR => return Error::Mem,
+            raw::RS_INPUT_ENDED => (io::ErrorKind::UnexpectedEof, "unexpected end of input file"),
+            raw::RS_BAD_MAGIC => return Error::BadMagic,
+            raw::RS_UNIMPLEMENTED => return Error::Unimplemented,
+            raw::RS_CORRUPT => return Error::Corrupt,
+            raw::RS_INTERNAL_ERROR => return Error::Internal,
+            raw::RS_PARAM_ERROR => return invalid_param("unknown", "bad parameter"),
+            n => return Error::Unknown(n),
+        };
+        io_err(kind, NativeError { code: err, msg })
+    }
+}
+
+impl SignatureType {
+    /// A signature file with MD4 signatures.
+    ///
+    /// Backward compatible with librsync < 1.0, but deprecated because of a security
+    /// vulnerability.
+    pub const MD4: SignatureType = SignatureType {
+        rolling: RollingKind::Rollsum,
+        digest: DigestKind::MD4,
+    };
+
+    /// A signature file using BLAKE2 hash.
+    #[allow(non_upper_case_globals)]
+    pub const Blake2: SignatureType = SignatureType {
+        rolling: RollingKind::Rollsum,
+        digest: DigestKind::Blake2,
+    };
+
+    // the canonical names, in the order of their magic numbers
+    const NAMES: [(&str, SignatureType); 4] = [
+        ("md4", SignatureType::MD4),
+        ("blake2", SignatureType::Blake2),
+        (
+            "rk-md4",
+            SignatureType {
+                rolling: RollingKind::RabinKarp,
+                digest: DigestKind::MD4,
+            },
+        ),
+        (
+            "rk-blake2",
+            SignatureType {
+                rolling: RollingKind::RabinKarp,
+                digest: DigestKind::Blake2,
+            },
+        ),
+    ];
+
+    /// Creates a signature type from its rolling checksum and its strong hash.
+    ///
+    /// `Error::Unimplemented` is returned if librsync defines no signature format for the
+    /// combination. Note that the linked librsync may still not support a defined signature
+    /// type, see `is_supported`.
+    pub fn new(rolling: RollingKind, digest: DigestKind) -> Result<Self> {
+        let sig_type = SignatureType { rolling, digest };
+        match Self::from_raw(sig_type.as_raw()) {
+            Some(_) => Ok(sig_type),
+            None => Err(Error::Unimplemented),
+        }
+    }
+
+    /// Returns the rolling checksum of this signature type.
+    pub fn rolling(self) -> RollingKind {
+        self.rolling
+    }
+
+    /// Returns the strong hash of this signature type.
+    pub fn digest(self) -> DigestKind {
+        self.digest
+    }
+
+    /// Returns the magic number starting the signatures of this type.
+    pub fn magic(self) -> u32 {
+        self.as_raw() as u32
+    }
+
+    fn as_raw(self) -> raw::rs_magic_number {
+        match (self.rolling, self.digest) {
+            (RollingKind::Rollsum, DigestKind::MD4) => raw::RS_MD4_SIG_MAGIC,
+            (RollingKind::Rollsum, DigestKind::Blake2) => raw::RS_BLAKE2_SIG_MAGIC,
+            (RollingKind::RabinKarp, DigestKind::MD4) => raw::RS_RK_MD4_SIG_MAGIC,
+            (RollingKind::RabinKarp, DigestKind::Blake2) => raw::RS_RK_BLAKE2_SIG_MAGIC,
+        }
+    }
+
+    /// Returns the exact length of a signature, in bytes.
+    ///
+    /// The signature of an input of `input_len` bytes, generated with this signature type and
+    /// the given block and strong signature lengths, is made by a fixed size header, followed by
+    /// an entry for every block, including the last partial one. A zero `strong_len` stands for
+    /// the full length of the strong signatures, as in `Signature::with_options`. The
+    /// `block_len` parameter must be greater than zero.
+    pub fn signature_len(self, block_len: usize, strong_len: usize, input_len: u64) -> u64 {
+        let strong_len = if strong_len == 0 {
+            self.max_strong_len()
+        } else {
+            strong_len
+        };
+        let block_len = block_len as u64;
+        let blocks = div_ceil(input_len, block_len);
+        SignatureInfo::HEADER_LEN as u64 + blocks * (4 + strong_len as u64)
+    }
+
+    /// Returns whether the linked librsync supports this signature type.
+    ///
+    /// Some builds of librsync leave out BLAKE2, and the ones older than 2.2 do not support
+    /// RabinKarp, so they fail to create signature jobs using them. This method probes librsync
+    /// by starting a signature job, which is immediately freed, so that callers can fall back to
+    /// MD4. The result of the probe is cached, so librsync is probed once per signature type.
+    pub fn is_supported(self) -> bool {
+        static PROBES: [OnceLock<bool>; 4] = [
+            OnceLock::new(),
+            OnceLock::new(),
+            OnceLock::new(),
+            OnceLock::new(),
+        ];
+
+        let probe = &PROBES[self.rolling as usize * 2 + self.digest as usize];
+        *probe.get_or_init(|| sig_begin(raw::RS_DEFAULT_BLOCK_LEN, 0, self).is_ok())
+    }
+
+    // the full length of the strong signatures
+    fn max_strong_len(self) -> usize {
+        self.digest.digest_len()
+    }
+
+    fn from_raw(magic: raw::rs_magic_number) -> Option<Self> {
+        let (rolling, digest) = match magic {
+            raw::RS_MD4_SIG_MAGIC => (RollingKind::Rollsum, DigestKind::MD4),
+            raw::RS_BLAKE2_SIG_MAGIC => (RollingKind::Rollsum, DigestKind::Blake2),
+            raw::RS_RK_MD4_SIG_MAGIC => (RollingKind::RabinKarp, DigestKind::MD4),
+            raw::RS_RK_BLAKE2_SIG_MAGIC => (RollingKind::RabinKarp, DigestKind::Blake2),
+            _ => return None,
+        };
+        Some(SignatureType { rolling, digest })
+    }
+}
+
+impl Display for SignatureType {
+    /// Writes the canonical name of the signature type, like `blake2` or `rk-md4`.
+    fn fmt(&self, fmt: &mut Formatter) -> fmt::Result {
+        let name = SignatureType::NAMES
+            .iter()
+            .find(|&&(_, sig_type)| sig_type == *self)
+            .map_or("unknown", |&(name, _)| name);
+        fmt.write_str(name)
+    }
+}
+
+impl FromStr for SignatureType {
+    type Err = Error;
+
+    /// Parses a signature type from its name, ignoring the case.
+    ///
+    /// The names are the ones written by `Display`: `md4`, `blake2`, `rk-md4` and `rk-blake2`.
+    /// Any other name gives an `ErrorKind::InvalidInput` error.
+    fn from_str(s: &str) -> Result<Self> {
+        SignatureType::NAMES
+            .iter()
+            .find(|&&(name, _)| name.eq_ignore_ascii_case(s))
+            .map(|&(_, sig_type)| sig_type)
+            .ok_or_else(|| {
+                io_err(
+                    io::ErrorKind::InvalidInput,
+                    format!("unknown signature type `{}`", s),
+                )
+            })
+    }
+}
+
+#[cfg(feature = "serde")]
+impl serde::Serialize for SignatureType {
+    fn serialize<S: serde::Serializer>(
+        &self,
+        serializer: S,
+    ) -> std::result::Result<S::Ok, S::Error> {
+        serializer.collect_str(self)
+    }
+}
+
+#[cfg(feature = "serde")]
+impl<'de> serde::Deserialize<'de> for SignatureType {
+    fn deserialize<D>(deserializer: D) -> std::result::Result<Self, D::Error>
+    where
+        D: serde::Deserializer<'de>,
+    {
+        let name = <String as serde::Deserialize>::deserialize(deserializer)?;
+        name.parse().map_err(serde::de::Error::custom)
+    }
+}
+
+impl Stats {
+    fn from_raw(stats: &raw::rs_stats_t) -> Self {
+        Stats {
+            lit_cmds: stats.lit_cmds as u64,
+            lit_bytes: stats.lit_bytes as u64,
+            lit_cmdbytes: stats.lit_cmdbytes as u64,
+            copy_cmds: stats.copy_cmds as u64,
+            copy_bytes: stats.copy_bytes as u64,
+            copy_cmdbytes: stats.copy_cmdbytes as u64,
+            sig_cmds: stats.sig_cmds as u64,
+            sig_bytes: stats.sig_bytes as u64,
+            false_matches: stats.false_matches as u64,
+            sig_blocks: stats.sig_blocks as u64,
+            block_len: stats.block_len,
+            in_bytes: stats.in_bytes as u64,
+            out_bytes: stats.out_bytes as u64,
+        }
+    }
+}
+
+impl Stats {
+    /// Returns the fraction of the new file copied from the basis file, by a delta.
+    ///
+    /// This is computed as the copied bytes over the total bytes of the new file, that are the
+    /// copied and literal bytes. It ranges from 0, when nothing matched the basis file, to 1,
+    /// when the new file is entirely made of basis blocks. It is 0 for an empty new file.
+    pub fn match_ratio(&self) -> f64 {
+        let new_len = self.copy_bytes + self.lit_bytes;
+        if new_len == 0 {
+            return 0.0;
+        }
+        self.copy_bytes as f64 / new_len as f64
+    }
+
+    /// Returns the size of a delta, relative to the size of the new file.
+    ///
+    /// This is computed as the output bytes of the delta over the total bytes of the new file,
+    /// that are the copied and literal bytes. Values lower than 1 mean that the delta is smaller
+    /// than the new file. It is 0 for an empty new file.
+    pub fn compression_ratio(&self) -> f64 {
+        let new_len = self.copy_bytes + self.lit_bytes;
+        if new_len == 0 {
+            return 0.0;
+        }
+        self.out_bytes as f64 / new_len as f64
+    }
+}
+
+impl SignatureInfo {
+    /// The length of a signature header, in bytes.
+    pub const HEADER_LEN: usize = 12;
+
+    /// Reads the signature parameters from the header of a signature.
+    ///
+    /// The `sig` parameter must contain at least the first `HEADER_LEN` bytes of a signature;
+    /// the remaining bytes, if any, are ignored. `Error::BadMagic` is returned if the header
+    /// does not start with a known signature magic number.
+    pub fn parse(sig: &[u8]) -> Result<Self> {
+        if sig.len() < Self::HEADER_LEN {
+            return Err(io_err(
+                io::ErrorKind::UnexpectedEof,
+                "signature header is truncated",
+            ));
+        }
+        let magic = read_be_u32(&sig[0..4]) as raw::rs_magic_number;
+        let sig_type = SignatureType::from_raw(magic).ok_or(Error::BadMagic)?;
+        Ok(SignatureInfo {
+            sig_type,
+            block_len: read_be_u32(&sig[4..8]) as usize,
+            strong_len: read_be_u32(&sig[8..12]) as usize,
+        })
+    }
+
+    /// Returns the number of blocks of a signature for an input of `total_bytes` bytes.
+    ///
+    /// Every `block_len` bytes of the input are described by a block, including the last
+    /// partial one. A zero `block_len`, which is never found in valid signatures, gives no
+    /// blocks. To count the blocks of a signature when the input length is unknown, see
+    /// `whole::count_blocks`.
+    pub fn block_count(&self, total_bytes: u64) -> u64 {
+        match self.block_len as u64 {
+            0 => 0,
+            block_len => div_ceil(total_bytes, block_len),
+        }
+    }
+
+    /// Estimates the memory librsync needs to load a signature with these parameters.
+    ///
+    /// The `sig_len` parameter is the length of the whole signature, in bytes, from which the
+    /// number of blocks is derived. The estimate follows the allocations librsync 2.x makes
+    /// when loading the signature and building its hash table: an entry for every block, with
+    /// the strong checksum padded to the pointer size, and a hash table with a power of two
+    /// number of slots, of one pointer and one key each, kept below 80% load. Small fixed-size
+    /// allocations are ignored. Together with `parse`, it allows to reject an oversized
+    /// signature before loading it.
+    pub fn estimated_hash_table_bytes(&self, sig_len: u64) -> u64 {
+        let ptr_len = mem::size_of::<usize>() as u64;
+        let blocks = sig_len.saturating_sub(Self::HEADER_LEN as u64) / (4 + self.strong_len as u64);
+        let block_sig_len = 4 + div_ceil(self.strong_len as u64, ptr_len) * ptr_len;
+        let slots = (1 + blocks * 5 / 4).next_power_of_two().max(2);
+        blocks * block_sig_len + slots * (ptr_len + 4)
+    }
+}
+
+impl<R: Read, S: Read> LazyDelta<R, S> {
+    /// Creates a new delta stream, loading the signature on first use.
+    ///
+    /// This constructor takes the new file stream (`new` parameter) and the signature of the
+    /// base file (`base_sig` parameter), like `Delta::new`, but does not read from them. If
+    /// loading the signature fails, the error is returned by the first read, and the following
+    /// reads fail as well.
+    pub fn new(new: R, base_sig: S) -> Self {
+        LazyDelta {
+            new: Some(new),
+            base_sig,
+            delta: None,
+        }
+    }
+
+    fn get(&mut self) -> io::Result<&mut Delta<BufReader<R>>> {
+        if self.delta.is_none() {
+            let new = self.new.take().ok_or_else(|| {
+                io::Error::new(io::ErrorKind::InvalidData, "signature failed to load")
+            })?;
+            let delta = Delta::new(new, &mut self.base_sig).map_err(|e| match e {
+                Error::Io(e) => e,
+                e => io::Error::new(io::ErrorKind::Other, e),
+            })?;
+            self.delta = Some(delta);
+        }
+        Ok(self.delta.as_mut().unwrap())
+    }
+}
+
+impl<R, S> LazyDelta<R, S> {
+    /// Returns whether the signature has been loaded.
+    pub fn is_loaded(&self) -> bool {
+        self.delta.is_some()
+    }
+
+    /// Unwraps this stream, returning the underlying delta stream if the signature has been
+    /// loaded, and the signature stream.
+    pub fn into_inner(self) -> (Option<Delta<BufReader<R>>>, S) {
+        (self.delta, self.base_sig)
+    }
+}
+
+impl<R: Read, S: Read> Read for LazyDelta<R, S> {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        self.get()?.read(buf)
+    }
+}
+
+impl<B, F: FnOnce() -> io::Result<B>> LazyBase<B, F> {
+    fn new(open: F) -> Self {
+        LazyBase {
+            open: Some(open),
+            base: None,
+        }
+    }
+
+    fn get(&mut self) -> io::Result<&mut B> {
+        if self.base.is_none() {
+            let open = self
+                .open
+                .take()
+                .ok_or_else(|| io::Error::new(io::ErrorKind::NotFound, "basis file not opened"))?;
+            self.base = Some(open()?);
+        }
+        Ok(self.base.as_mut().unwrap())
+    }
+}
+
+impl<B, F> LazyBase<B, F> {
+    /// Returns whether the basis file has been opened.
+    pub fn is_open(&self) -> bool {
+        self.base.is_some()
+    }
+
+    /// Unwraps this basis, returning the basis file if it has been opened.
+    pub fn into_inner(self) -> Option<B> {
+        self.base
+    }
+}
+
+impl<B: Read, F: FnOnce() -> io::Result<B>> Read for LazyBase<B, F> {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        self.get()?.read(buf)
+    }
+}
+
+impl<B: Seek, F: FnOnce() -> io::Result<B>> Seek for LazyBase<B, F> {
+    fn seek(&mut self, pos: io::SeekFrom) -> io::Result<u64> {
+        self.get()?.seek(pos)
+    }
+}
+
+impl<B> PositionedBase<B> {
+    fn new(inner: B) -> Self {
+        PositionedBase { inner, pos: None }
+    }
+
+    fn into_inner(self) -> B {
+        self.inner
+    }
+}
+
+impl<B: Read> Read for PositionedBase<B> {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        let pos = self.pos.take();
+        let read = self.inner.read(buf)?;
+        self.pos = pos.map(|p| p + read as u64);
+        Ok(read)
+    }
+}
+
+impl<B: Seek> Seek for PositionedBase<B> {
+    fn seek(&mut self, pos: io::SeekFrom) -> io::Result<u64> {
+        if let (io::SeekFrom::Start(target), Some(current)) = (pos, self.pos) {
+            if target == current {
+                return Ok(current);
+            }
+        }
+        self.pos = None;
+        let current = self.inner.seek(pos)?;
+        self.pos = Some(current);
+        Ok(current)
+    }
+}
+
+impl Drop for Sumset {
+    fn drop(&mut self) {
+        unsafe {
+            if !self.0.is_null() {
+                raw::rs_free_sumset(self.0);
+            }
+        }
+    }
+}
+
+impl Deref for Sumset {
+    type Target = *mut raw::rs_signature_t;
+    fn deref(&self) -> &Self::Target {
+        &self.0
+    }
+}
+
+unsafe impl Send for Sumset {}
+
+extern "C" fn patch_copy_cb(
+    opaque: *mut libc::c_void,
+    pos: raw::rs_long_t,
+    len: *mut libc::size_t,
+    buf: *mut *mut libc::c_void,
+) -> raw::rs_result {
+    // unwinding into librsync is undefined behavior, so a panic is reported as an IO error
+    panic::catch_unwind(AssertUnwindSafe(|| patch_copy(opaque, pos, len, buf)))
+        .unwrap_or(raw::RS_IO_ERROR)
+}
+
+fn patch_copy(
+    opaque: *mut libc::c_void,
+    pos: raw::rs_long_t,
+    len: *mut libc::size_t,
+    buf: *mut *mut libc::c_void,
+) -> raw::rs_result {
+    let source = unsafe { &*(opaque as *const CopySource) };
+    let mut input: RefMut<dyn ReadAndSeek> = source.base.borrow_mut();
+    let output = unsafe {
+        let buf: *mut u8 = mem::transmute(*buf);
+        slice::from_raw_parts_mut(buf, *len)
+    };
+    // librsync offsets are signed 64 bits integers, a negative one must not wrap around
+    let pos = try_or_rs_error!(u64::try_from(pos));
+    #[cfg(feature = "copy-hook")]
+    {
+        if let Some(ref mut on_copy) = *source.on_copy.borrow_mut() {
+            on_copy(pos, output.len());
+        }
+    }
+    let mut attempts = 0;
+    let read = loop {
+        match read_at(&mut *input, pos, output) {
+            Ok(read) => break read,
+            Err(ref e) if attempts < source.retries && is_transient(e) => attempts += 1,
+            Err(_) => return raw::RS_IO_ERROR,
+        }
+    };
+    if read == 0 {
+        // the copy command goes beyond the end of the base
+        return raw::RS_INPUT_ENDED;
+    }
+    if let Some(ref mut copies) = *source.copies.borrow_mut() {
+        match copies.last_mut() {
+            Some(Command::Copy { offset, len }) if *offset + *len == pos => *len += read as u64,
+            _ => copies.push(Command::Copy {
+                offset: pos,
+                len: read as u64,
+            }),
+        }
+    }
+    unsafe {
+        *len = read;
+    }
+    raw::RS_DONE
+}
+
+fn read_at(input: &mut dyn ReadAndSeek, pos: u64, output: &mut [u8]) -> io::Result<usize> {
+    input.seek(io::SeekFrom::Start(pos))?;
+    input.read(output)
+}
+
+fn is_transient(e: &io::Error) -> bool {
+    matches!(
+        e.kind(),
+        io::ErrorKind::Interrupted | io::ErrorKind::WouldBlock
+    )
+}
+
+// Loads a signature in memory, and builds its hash table.
+fn load_signature<S: Read + ?Sized>(base_sig: &mut S) -> Result<Sumset> {
+    logfwd::init();
+
+    let mut sumset = Sumset(ptr::null_mut());
+    {
+        let job = unsafe { raw::rs_loadsig_begin(&mut sumset.0) };
+        if job.is_null() {
+            return Err(Error::Mem);
+        }
+        let mut job = JobDriver::new(BufReader::new(base_sig), Job::new(job));
+        job.consume_input()?;
+    }
+    build_hash_table(sumset)
+}
+
+fn build_hash_table(sumset: Sumset) -> Result<Sumset> {
+    if sumset.is_null() {
+        return Err(invalid_param("sig", "null signature given"));
+    }
+    let res = unsafe { raw::rs_build_hash_table(*sumset) };
+    if res != raw::RS_DONE {
+        return Err(Error::from(res));
+    }
+    Ok(sumset)
+}
+
+fn sig_begin(block_len: usize, strong_len: usize, sig_type: SignatureType) -> Result<Job> {
+    logfwd::init();
+
+    let block_len = default_block_len(block_len);
+    // the block length is stored in 32 bits in the signature header
+    if u32::try_from(block_len).is_err() {
+        return Err(invalid_param("block_len", "block length too long"));
+    }
+    if strong_len > sig_type.max_strong_len() {
+        return Err(invalid_param(
+            "strong_len",
+            "strong signature length too long",
+        ));
+    }
+    // the magic number is always known, since it comes from a `SignatureType`, so a failure is
+    // due to memory allocation, or to a signature type not supported by the linked librsync
+    let job = unsafe { raw::rs_sig_begin(block_len, strong_len, sig_type.as_raw()) };
+    if job.is_null() {
+        return Err(Error::Mem);
+    }
+    Ok(Job::new(job))
+}
+
+// Divides rounding up, since `u64::div_ceil` is too recent for the supported Rust versions.
+fn div_ceil(n: u64, d: u64) -> u64 {
+    n / d + (n % d != 0) as u64
+}
+
+// Replaces a zero block length with the default one, like librsync does for its own tools.
+fn default_block_len(block_len: usize) -> usize {
+    if block_len == 0 {
+        raw::RS_DEFAULT_BLOCK_LEN
+    } else {
+        block_len
+    }
+}
+
+// Reads the next block entry of a signature, or `None` at the end of the signature.
+fn read_block_sig<R: Read + ?Sized>(input: &mut R, strong_len: usize) -> Result<Option<BlockSig>> {
+    let mut entry = vec![0; 4 + strong_len];
+    let mut read = 0;
+    while read < entry.len() {
+        match input.read(&mut entry[read..]) {
+            Ok(0) => break,
+            Ok(n) => read += n,
+            Err(ref e) if e.kind() == io::ErrorKind::Interrupted => (),
+            Err(e) => return Err(e.into()),
+        }
+    }
+    if read == 0 {
+        return Ok(None);
+    }
+    if read < entry.len() {
+        return Err(io_err(
+            io::ErrorKind::UnexpectedEof,
+            "signature block is truncated",
+        ));
+    }
+    Ok(Some(BlockSig {
+        weak_sum: read_be_u32(&entry[..4]),
+        strong_sum: entry.split_off(4),
+    }))
+}
+
+fn read_be_u32(buf: &[u8]) -> u32 {
+    buf.iter().fold(0, |acc, &b| (acc << 8) | u32::from(b))
+}
+
+fn invalid_param<D: Into<String>>(name: &'static str, detail: D) -> Error {
+    Error::InvalidParameter {
+        name,
+        detail: detail.into(),
+    }
+}
+
+fn io_err<E>(kind: io::ErrorKind, e: E) -> Error
+where
+    E: Into<Box<dyn error::Error + Send + Sync>>,
+{
+    Error::Io(io::Error::new(kind, e))
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use std::cell::Cell;
+    use std::io::{Cursor, Read, Write};
+    use std::sync::atomic::{AtomicUsize, Ordering};
+    use std::sync::{Arc, Mutex};
+    use std::thread;
+
+    const DATA: &'static str = "this is a string to be tested";
+    const DATA2: &'static str = "this is another string to be tested";
+
+    // generated with `rdiff signature -b 10 -S 5 data data.sig`
+    fn data_signature() -> Vec<u8> {
+        vec![
+            0x72, 0x73, 0x01, 0x36, 0x00, 0x00, 0x00, 0x0a, 0x00, 0x00, 0x00, 0x05, 0x1b, 0x21,
+            0x04, 0x8b, 0xad, 0x3c, 0xbd, 0x19, 0x09, 0x1d, 0x1b, 0x04, 0xf0, 0x9d, 0x1f, 0x64,
+            0x31, 0xde, 0x15, 0xf4, 0x04, 0x87, 0x60, 0x96, 0x19, 0x50, 0x39,
+        ]
+    }
+
+    // generated with `rdiff delta data.sig data2 data2.delta`
+    fn data2_delta() -> Vec<u8> {
+        vec![
+            0x72, 0x73, 0x02, 0x36, 0x10, 0x74, 0x68, 0x69, 0x73, 0x20, 0x69, 0x73, 0x20, 0x61,
+            0x6e, 0x6f, 0x74, 0x68, 0x65, 0x72, 0x20, 0x45, 0x0a, 0x13, 0x00,
+        ]
+    }
+
+    #[test]
+    fn signature() {
+        let cursor = Cursor::new(DATA);
+        let mut sig = Signature::with_options(cursor, 10, 5, SignatureType::MD4).unwrap();
+        let mut signature = Vec::new();
+        let read = sig.read_to_end(&mut signature).unwrap();
+        assert_eq!(read, signature.len());
+        assert_eq!(signature, data_signature());
+        sig.into_inner();
+    }
+
+    #[test]
+    fn signature_partial_reads() {
+        // feed one input byte at a time, so each iteration produces a little output
+        let input = BufReader::with_capacity(1, Cursor::new(DATA));
+        let mut sig = Signature::with_buf_read(input, 10, 5, SignatureType::MD4).unwrap();
+        let mut signature = Vec::new();
+        let mut reads = 0;
+        loop {
+            let mut buf = [0; 1024];
+            let read = sig.read(&mut buf).unwrap();
+            if read == 0 {
+                break;
+            }
+            assert!(read < data_signature().len());
+            signature.extend_from_slice(&buf[..read]);
+            reads += 1;
+        }
+        assert!(reads > 1);
+        assert_eq!(signature, data_signature());
+    }
+
+    #[test]
+    fn signature_writer() {
+        let mut sig = SignatureWriter::with_options(Vec::new(), 10, 5, SignatureType::MD4).unwrap();
+        for chunk in DATA.as_bytes().chunks(3) {
+            sig.write_all(chunk).unwrap();
+        }
+        let signature = sig.finish().unwrap();
+        assert_eq!(signature, data_signature());
+
+        // the signature is larger than the input, so writes often only flush pending output
+        let data = (0..1 << 18).map(|i| (i % 251) as u8).collect::<Vec<_>>();
+        let mut expected = Vec::new();
+        Signature::with_options(&data[..], 16, 32, SignatureType::Blake2)
+            .unwrap()
+            .read_to_end(&mut expected)
+            .unwrap();
+        let mut sig =
+            SignatureWriter::with_options(Vec::new(), 16, 32, SignatureType::Blake2).unwrap();
+        for chunk in data.chunks(1 << 16) {
+            sig.write_all(chunk).unwrap();
+        }
+        assert_eq!(sig.stats().in_bytes, data.len() as u64);
+        assert!(sig.stats().out_bytes > 0);
+        let signature = sig.finish().unwrap();
+        assert_eq!(signature, expected);
+    }
+
+    #[test]
+    fn signature_pending_output() {
+        let cursor = Cursor::new(DATA);
+        let mut sig = Signature::with_options(cursor, 10, 5, SignatureType::MD4).unwrap();
+        assert_eq!(sig.pending_output_len(), 0);
+        let mut buf = [0; 4];
+        sig.read_exact(&mut buf).unwrap();
+        let pending = sig.pending_output_len();
+        assert!(pending > 0);
+        let mut rest = Vec::new();
+        sig.read_to_end(&mut rest).unwrap();
+        assert!(rest.len() >= pending);
+        assert_eq!(sig.pending_output_len(), 0);
+    }
+
+    #[test]
+    fn signature_out_buf_len() {
+        let data = (0..1 << 20).map(|i| (i % 251) as u8).collect::<Vec<_>>();
+        let builder = SignatureBuilder::new().block_len(256).in_buf_len(1 << 16);
+        let iterations = |out_buf_len| {
+            let mut sig = builder
+                .out_buf_len(out_buf_len)
+                .build(Cursor::new(&data))
+                .unwrap();
+            let mut signature = Vec::new();
+            sig.read_to_end(&mut signature).unwrap();
+            (signature, sig.driver.iterations())
+        };
+        let (small_sig, small_iters) = iterations(64);
+        let (large_sig, large_iters) = iterations(1 << 16);
+        assert_eq!(small_sig, large_sig);
+        assert!(large_iters < small_iters);
+        assert!(SignatureBuilder::new()
+            .out_buf_len(0)
+            .build(Cursor::new(&data))
+            .is_err());
+    }
+
+    #[test]
+    fn signature_len() {
+        for &sig_type in &[SignatureType::MD4, SignatureType::Blake2] {
+            for &(block_len, strong_len) in &[(10, 5), (16, 0), (2048, 8)] {
+                for &input_len in &[0, 1, 9, 10, 11, 29, 160, 4097] {
+                    let input = vec![7; input_len];
+                    let mut sig =
+                        Signature::with_options(&input[..], block_len, strong_len, sig_type)
+                            .unwrap();
+                    let mut signature = Vec::new();
+                    sig.read_to_end(&mut signature).unwrap();
+                    assert_eq!(
+                        sig_type.signature_len(block_len, strong_len, input_len as u64),
+                        signature.len() as u64
+                    );
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn signature_is_done() {
+        let cursor = Cursor::new(DATA);
+        let mut sig = Signature::with_options(cursor, 10, 5, SignatureType::MD4).unwrap();
+        assert!(!sig.is_done());
+        let mut buf = [0; 1];
+        sig.read_exact(&mut buf).unwrap();
+        assert!(!sig.is_done());
+        let mut signature = Vec::new();
+        sig.read_to_end(&mut signature).unwrap();
+        assert!(sig.is_done());
+    }
+
+    #[test]
+    fn signature_tee() {
+        let mut tee =
+            SignatureTee::with_options(Cursor::new(DATA), 10, 5, SignatureType::MD4).unwrap();
+        let mut passed = String::new();
+        tee.read_to_string(&mut passed).unwrap();
+        assert_eq!(passed, DATA);
+        let (input, signature) = tee.finish().unwrap();
+        assert_eq!(input.position(), DATA.len() as u64);
+        assert_eq!(signature, data_signature());
+    }
+
+    #[test]
+    fn delta_resume() {
+        let base: Vec<u8> = (0..20_000u32).map(|i| (i % 253) as u8).collect();
+        let mut new = base.clone();
+        new[5_000..5_100].copy_from_slice(&[0xaa; 100]);
+        new[15_000..15_500].copy_from_slice(&[0xbb; 500]);
+        let mut sig = Vec::new();
+        Signature::with_options(&base[..], 64, 8, SignatureType::Blake2)
+            .unwrap()
+            .read_to_end(&mut sig)
+            .unwrap();
+
+        let mut full = Vec::new();
+        Delta::new(&new[..], &mut &sig[..])
+            .unwrap()
+            .read_to_end(&mut full)
+            .unwrap();
+
+        // interrupted after some output
+        let mut delta = Delta::new(&new[..], &mut &sig[..]).unwrap();
+        let mut out = vec![0; 40];
+        delta.read_exact(&mut out).unwrap();
+        let checkpoint = delta.checkpoint();
+        assert!(checkpoint.input_offset > 0);
+        drop(delta);
+
+        out.truncate(checkpoint.output_len as usize);
+        Delta::resume(&new[..], &mut &sig[..], checkpoint)
+            .unwrap()
+            .copy_to(&mut out)
+            .unwrap();
+
+        let mut patched = Vec::new();
+        Patch::new(Cursor::new(&base), &out[..])
+            .unwrap()
+            .read_to_end(&mut patched)
+            .unwrap();
+        assert_eq!(patched, new);
+        let mut expected = Vec::new();
+        Patch::new(Cursor::new(&base), &full[..])
+            .unwrap()
+            .read_to_end(&mut expected)
+            .unwrap();
+        assert_eq!(patched, expected);
+    }
+
+    #[test]
+    fn signature_entries() {
+        let mut sig = Signature::with_options(DATA.as_bytes(), 10, 5, SignatureType::MD4).unwrap();
+        let entries = sig.read_entries().unwrap();
+        assert_eq!(entries.len(), 3);
+        assert_eq!(entries[0].weak_sum, 0x1b21_048b);
+        assert_eq!(entries[0].strong_sum, vec![0xad, 0x3c, 0xbd, 0x19, 0x09]);
+        assert!(entries.iter().all(|entry| entry.strong_sum.len() == 5));
+    }
+
+    // A reader failing after a given number of bytes.
+    struct FailingReader<T> {
+        inner: T,
+        left: usize,
+    }
+
+    impl<T: Read> Read for FailingReader<T> {
+        fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+            if self.left == 0 {
+                return Err(io::Error::from(io::ErrorKind::ConnectionReset));
+            }
+            let len = buf.len().min(self.left);
+            let read = self.inner.read(&mut buf[..len])?;
+            self.left -= read;
+            Ok(read)
+        }
+    }
+
+    #[test]
+    fn delta_format_version() {
+        let delta = Delta::new(DATA2.as_bytes(), &mut Cursor::new(data_signature())).unwrap();
+        let fixture = data2_delta();
+        assert_eq!(delta.format_version(), fixture[2]);
+        let magic = [fixture[0], fixture[1], fixture[2], fixture[3]];
+        assert_eq!(command::format_version(magic).unwrap(), 2);
+        assert_eq!(
+            command::format_version([0x72, 0x73, 0x03, 0x36]).unwrap(),
+            3
+        );
+        match command::format_version([0x72, 0x73, 0x01, 0x36 + 1]) {
+            Err(Error::BadMagic) => (),
+            _ => panic!("expected a bad magic error"),
+        }
+    }
+
+    #[test]
+    fn delta_fraction_progress() {
+        let data = (0..100_000).map(|i| (i % 251) as u8).collect::<Vec<_>>();
+        let mut sig = Vec::new();
+        Signature::with_options(&data[..], 64, 8, SignatureType::Blake2)
+            .unwrap()
+            .read_to_end(&mut sig)
+            .unwrap();
+        let new = data.iter().map(|b| b.wrapping_add(1)).collect::<Vec<_>>();
+        let fractions = Arc::new(Mutex::new(Vec::new()));
+        let recorded = fractions.clone();
+        let mut delta = Delta::new(&new[..], &mut &sig[..])
+            .unwrap()
+            .with_fraction_progress(new.len() as u64, move |f| recorded.lock().unwrap().push(f));
+        delta.read_to_end(&mut Vec::new()).unwrap();
+
+        let fractions = fractions.lock().unwrap();
+        assert!(fractions.len() > 2);
+        assert!(fractions.len() < new.len() / 1000);
+        assert!(fractions.windows(2).all(|w| w[0] < w[1]));
+        assert!(fractions[0] > 0.0);
+        assert_eq!(*fractions.last().unwrap(), 1.0);
+    }
+
+    #[test]
+    fn delta_compute() {
+        let (delta, stats) =
+            Delta::compute(DATA2.as_bytes(), Cursor::new(data_signature())).unwrap();
+        let mut expected = Vec::new();
+        Delta::new(DATA2.as_bytes(), &mut Cursor::new(data_signature()))
+            .unwrap()
+            .read_to_end(&mut expected)
+            .unwrap();
+        assert_eq!(delta, expected);
+        assert_eq!(stats.lit_bytes + stats.copy_bytes, DATA2.len() as u64);
+        assert_eq!(stats.out_bytes, delta.len() as u64);
+    }
+
+    #[test]
+    fn delta_input_consumed() {
+        let data = (0..20_000).map(|i| (i % 251) as u8).collect::<Vec<_>>();
+        let mut sig = Vec::new();
+        Signature::with_options(&data[..], 512, 8, SignatureType::Blake2)
+            .unwrap()
+            .read_to_end(&mut sig)
+            .unwrap();
+
+        let new = FailingReader {
+            inner: &data[..],
+            left: 5000,
+        };
+        let mut delta = Delta::new(new, &mut &sig[..]).unwrap();
+        assert_eq!(delta.input_consumed(), 0);
+        let err = delta.read_to_end(&mut Vec::new()).unwrap_err();
+        assert_eq!(err.kind(), io::ErrorKind::ConnectionReset);
+        assert_eq!(delta.input_consumed(), 5000);
+
+        let mut delta = Delta::new(&data[..], &mut &sig[..]).unwrap();
+        delta.read_to_end(&mut Vec::new()).unwrap();
+        assert_eq!(delta.input_consumed(), data.len() as u64);
+    }
+
+    #[test]
+    fn delta_deadline() {
+        let new = vec![3; 4 * 1024 * 1024];
+        let mut delta =
+            Delta::new_with_deadline(&new[..], &mut Cursor::new(data_signature()), Instant::now())
+                .unwrap();
+        let err = delta.read_to_end(&mut Vec::new()).unwrap_err();
+        assert_eq!(err.kind(), io::ErrorKind::TimedOut);
+
+        let deadline = Instant::now() + std::time::Duration::from_secs(60);
+        let mut delta =
+            Delta::new_with_deadline(&new[..], &mut Cursor::new(data_signature()), deadline)
+                .unwrap();
+        delta.read_to_end(&mut Vec::new()).unwrap();
+    }
+
+    #[test]
+    fn delta_commands() {
+        let commands: Vec<_> = DeltaCommands::new(&data2_delta()[..])
+            .collect::<Result<_>>()
+            .unwrap();
+        assert_eq!(
+            commands,
+            vec![
+                Command::Literal(b"this is another ".to_vec()),
+                Command::Copy {
+                    offset: 10,
+                    len: 19
+                },
+            ]
+        );
+
+        let mut literals = Vec::new();
+        DeltaCommands::new(&data2_delta()[..])
+            .for_each_literal(|data| literals.extend_from_slice(data))
+            .unwrap();
+        assert_eq!(literals, b"this is another ");
+
+        let mut literals = Vec::new();
+        let res = DeltaCommands::new(&data_signature()[..])
+            .for_each_literal(|data| literals.extend_from_slice(data));
+        match res {
+            Err(Error::BadMagic) => (),
+            _ => panic!("expected bad magic error"),
+        }
+    }
+
+    #[test]
+    fn corrupt_delta() {
+        // a reserved command
+        let delta = [0x72, 0x73, 0x02, 0x36, 0x60, 0x00];
+        let mut patch = Patch::new(Cursor::new(DATA), &delta[..]).unwrap();
+        let err = patch.read_to_end(&mut Vec::new()).unwrap_err();
+        match Error::from(err) {
+            Error::Corrupt => (),
+            e => panic!("expected corrupt error, got {:?}", e),
+        }
+        match whole::patch(&mut Cursor::new(DATA), &mut &delta[..], &mut Vec::new()) {
+            Err(Error::Corrupt) => (),
+            res => panic!("expected corrupt error, got {:?}", res),
+        }
+    }
+
+    #[test]
+    fn signature_append() {
+        let data: Vec<u8> = (0..5000u32).map(|i| (i % 239) as u8).collect();
+        let sign = |data: &[u8]| {
+            let mut sig = Vec::new();
+            Signature::with_options(data, 100, 0, SignatureType::Blake2)
+                .unwrap()
+                .read_to_end(&mut sig)
+                .unwrap();
+            sig
+        };
+        let prev = sign(&data[..3000]);
+        let sig =
+            Signature::append(&prev, 3000, &data[3000..], 100, 0, SignatureType::Blake2).unwrap();
+        assert_eq!(sig, sign(&data));
+
+        // not on a block boundary
+        let prev = sign(&data[..2950]);
+        assert!(
+            Signature::append(&prev, 2950, &data[2950..], 100, 0, SignatureType::Blake2).is_err()
+        );
+        // different parameters
+        assert!(
+            Signature::append(&prev, 3000, &data[3000..], 50, 0, SignatureType::Blake2).is_err()
+        );
+        assert!(Signature::append(&prev, 3000, &data[3000..], 100, 0, SignatureType::MD4).is_err());
+    }
+
+    #[test]
+    fn error_codes() {
+        let err = Error::from(raw::RS_SYNTAX_ERROR);
+        assert_eq!(err.to_string(), "syntax error (librsync code 101)");
+        assert_eq!(err.code(), Some(raw::RS_SYNTAX_ERROR));
+        assert_eq!(
+            Error::from(raw::RS_PARAM_ERROR).code(),
+            Some(raw::RS_PARAM_ERROR)
+        );
+        assert_eq!(Error::WeakChecksum.code(), None);
+        assert_eq!(
+            Error::Io(io::Error::from(io::ErrorKind::Other)).code(),
+            None
+        );
+
+        // a delta is not a signature
+        let err = match Delta::new(DATA.as_bytes(), &mut &data2_delta()[..]) {
+            Err(err) => err,
+            Ok(_) => panic!("expected an error"),
+        };
+        assert_eq!(err.code(), Some(raw::RS_CORRUPT));
+
+        // errors reported by this crate show no librsync code
+        assert_eq!(
+            Error::Corrupt.to_string(),
+            "unbelievable value in stream, input is corrupt"
+        );
+        assert_eq!(
+            Error::Unknown(999).to_string(),
+            "unknown error from native library (librsync code 999)"
+        );
+    }
+
+    #[test]
+    fn invalid_parameters() {
+        let name = |res: Result<Signature<_>>| match res {
+            Err(Error::InvalidParameter { name, .. }) => name,
+            _ => panic!("expected an invalid parameter error"),
+        };
+        let builder = SignatureBuilder::new();
+        assert_eq!(
+            name(builder.in_buf_len(0).build(DATA.as_bytes())),
+            "in_buf_len"
+        );
+        assert_eq!(
+            name(builder.out_buf_len(0).build(DATA.as_bytes())),
+            "out_buf_len"
+        );
+        let too_long = SignatureType::Blake2.max_strong_len() + 1;
+        assert_eq!(
+            name(builder.strong_len(too_long).build(DATA.as_bytes())),
+            "strong_len"
+        );
+        let res = Signature::with_options(DATA.as_bytes(), 10, 17, SignatureType::MD4);
+        assert_eq!(name(res), "strong_len");
+        if usize::MAX > u32::MAX as usize {
+            let res = builder
+                .block_len(u32::MAX as usize + 1)
+                .build(DATA.as_bytes());
+            assert_eq!(name(res), "block_len");
+        }
+
+        let prev = data_signature();
+        match Signature::append(&prev, 25, DATA.as_bytes(), 10, 5, SignatureType::MD4) {
+            Err(Error::InvalidParameter {
+                name: "prev_len", ..
+            }) => (),
+            _ => panic!("expected an invalid parameter error"),
+        }
+        let err = invalid_param("block_len", "zero block length");
+        assert_eq!(
+            err.to_string(),
+            "invalid parameter `block_len`: zero block length"
+        );
+    }
+
+    #[test]
+    fn error_codes_mapped() {
+        let errors = [
+            raw::RS_BLOCKED,
+            raw::RS_IO_ERROR,
+            raw::RS_SYNTAX_ERROR,
+            raw::RS_MEM_ERROR,
+            raw::RS_INPUT_ENDED,
+            raw::RS_BAD_MAGIC,
+            raw::RS_UNIMPLEMENTED,
+            raw::RS_CORRUPT,
+            raw::RS_INTERNAL_ERROR,
+            raw::RS_PARAM_ERROR,
+        ];
+        for &code in &errors {
+            let err = Error::from(code);
+            assert_ne!(err.kind(), ErrorKind::Unknown, "code {}", code);
+            assert_eq!(err.code(), Some(code));
+        }
+        // librsync error codes are contiguous, a new one would show up here
+        assert_eq!(
+            raw::RS_PARAM_ERROR - raw::RS_IO_ERROR + 1,
+            errors.len() as i32 - 1
+        );
+        match Error::from(raw::RS_PARAM_ERROR + 1) {
+            Error::Unknown(_) => (),
+            _ => panic!("expected unknown error"),
+        }
+
+        // result codes which are not errors
+        for &code in &[raw::RS_DONE, raw::RS_RUNNING, raw::RS_TEST_SKIPPED] {
+            match Error::from(code) {
+                Error::Unknown(n) => assert_eq!(n, code),
+                _ => panic!("expected unknown error"),
+            }
+        }
+    }
+
+    #[test]
+    fn error_kinds() {
+        let io = io::Error::from(io::ErrorKind::Other);
+        assert_eq!(Error::Io(io).kind(), ErrorKind::Io);
+        assert_eq!(Error::from(raw::RS_SYNTAX_ERROR).kind(), ErrorKind::Syntax);
+        assert_eq!(
+            Error::from(raw::RS_PARAM_ERROR).kind(),
+            ErrorKind::InvalidParameter
+        );
+        assert_eq!(Error::from(raw::RS_INPUT_ENDED).kind(), ErrorKind::Io);
+        assert_eq!(Error::Mem.kind(), ErrorKind::Mem);
+        assert_eq!(Error::BadMagic.kind(), ErrorKind::BadMagic);
+        assert_eq!(Error::WeakChecksum.kind(), ErrorKind::WeakChecksum);
+        assert_eq!(Error::Corrupt.kind(), ErrorKind::Corrupt);
+        assert_eq!(Error::Unimplemented.kind(), ErrorKind::Unimplemented);
+        assert_eq!(Error::Internal.kind(), ErrorKind::Internal);
+        assert_eq!(Error::Unknown(999).kind(), ErrorKind::Unknown);
+        assert_eq!(ErrorKind::Io as i32, 1);
+        assert_eq!(ErrorKind::Unknown as i32, 8);
+    }
+
+    #[test]
+    fn copy_to() {
+        let mut expected = Vec::new();
+        Patch::new(Cursor::new(DATA), Cursor::new(data2_delta()))
+            .unwrap()
+            .read_to_end(&mut expected)
+            .unwrap();
+
+        let mut patch = Patch::new(Cursor::new(DATA), Cursor::new(data2_delta())).unwrap();
+        let mut out = Vec::new();
+        assert_eq!(patch.copy_to(&mut out).unwrap(), DATA2.len() as u64);
+        assert_eq!(out, expected);
+
+        // mid-stream
+        let mut sig = Signature::with_options(DATA.as_bytes(), 10, 5, SignatureType::MD4).unwrap();
+        let mut out = vec![0; 7];
+        sig.read_exact(&mut out).unwrap();
+        let written = sig.copy_to(&mut out).unwrap();
+        assert_eq!(written, data_signature().len() as u64 - 7);
+        assert_eq!(out, data_signature());
+        assert_eq!(sig.copy_to(&mut out).unwrap(), 0);
+
+        let mut delta = Delta::new(DATA2.as_bytes(), &mut Cursor::new(data_signature())).unwrap();
+        let mut out = Vec::new();
+        delta.copy_to(&mut out).unwrap();
+        assert_eq!(out, data2_delta());
+    }
+
+    #[test]
+    fn signature_chunks() {
+        let chunks = DATA
+            .as_bytes()
+            .chunks(7)
+            .map(|chunk| Ok(chunk.to_vec()))
+            .chain(Some(Ok(Vec::new())));
+        let mut sig =
+            Signature::with_options(ChunkReader::new(chunks), 10, 5, SignatureType::MD4).unwrap();
+        let mut signature = Vec::new();
+        sig.read_to_end(&mut signature).unwrap();
+        assert_eq!(signature, data_signature());
+
+        let chunks = vec![
+            Ok(b"data".to_vec()),
+            Err(io::Error::from(io::ErrorKind::BrokenPipe)),
+        ];
+        let mut sig = Signature::new(ChunkReader::new(chunks.into_iter())).unwrap();
+        assert!(sig.read_to_end(&mut Vec::new()).is_err());
+    }
+
+    #[test]
+    fn multi_segments() {
+        let data = (0..10_000).map(|i| (i % 251) as u8).collect::<Vec<_>>();
+        let mut new = data.clone();
+        new[5000..5100].copy_from_slice(&[0; 100]);
+        let bounds = [0, 1000, 1000, 4096, 9999, 10_000];
+
+        let mut sig = Vec::new();
+        Signature::with_options(&data[..], 256, 8, SignatureType::Blake2)
+            .unwrap()
+            .read_to_end(&mut sig)
+            .unwrap();
+        let readers = bounds
+            .windows(2)
+            .map(|w| Box::new(&data[w[0]..w[1]]) as Box<dyn Read + '_>)
+            .collect();
+        let mut multi_sig = Vec::new();
+        Signature::with_options(MultiReader::new(readers), 256, 8, SignatureType::Blake2)
+            .unwrap()
+            .read_to_end(&mut multi_sig)
+            .unwrap();
+        assert_eq!(multi_sig, sig);
+
+        let mut delta = Vec::new();
+        Delta::new(&new[..], &mut &sig[..])
+            .unwrap()
+            .read_to_end(&mut delta)
+            .unwrap();
+        let segments = bounds
+            .windows(2)
+            .map(|w| {
+                let segment = Cursor::new(&data[w[0]..w[1]]);
+                (
+                    Box::new(segment) as Box<dyn ReadAndSeek + '_>,
+                    (w[1] - w[0]) as u64,
+                )
+            })
+            .collect();
+        let base = MultiSeekReader::new(segments);
+        assert_eq!(base.len(), data.len() as u64);
+        let mut patched = Vec::new();
+        Patch::new(base, &delta[..])
+            .unwrap()
+            .read_to_end(&mut patched)
+            .unwrap();
+        assert_eq!(patched, new);
+
+        // a segment shorter than declared
+        let segments = vec![(Box::new(Cursor::new(DATA)) as Box<dyn ReadAndSeek>, 40)];
+        let mut base = MultiSeekReader::new(segments);
+        base.seek(io::SeekFrom::End(-5)).unwrap();
+        assert!(base.read(&mut [0; 8]).is_err());
+        assert!(base.seek(io::SeekFrom::Current(-100)).is_err());
+    }
+
+    #[test]
+    fn signature_range() {
+        let data: Vec<u8> = (0..10_000u32).map(|i| (i % 251) as u8).collect();
+        let mut sig = Vec::new();
+        Signature::new_range(Cursor::new(&data), 3000, 4000, 64, 8, SignatureType::MD4)
+            .unwrap()
+            .read_to_end(&mut sig)
+            .unwrap();
+        let mut expected = Vec::new();
+        Signature::with_options(&data[3000..7000], 64, 8, SignatureType::MD4)
+            .unwrap()
+            .read_to_end(&mut expected)
+            .unwrap();
+        assert_eq!(sig, expected);
+
+        let res = Signature::new_range(Cursor::new(&data), 9000, 2000, 64, 8, SignatureType::MD4);
+        match res {
+            Err(Error::Io(ref e)) if e.kind() == io::ErrorKind::UnexpectedEof => (),
+            _ => panic!("expected an UnexpectedEof error"),
+        }
+    }
+
+    #[test]
+    fn signature_into_inner_seek() {
+        let data = vec![1; 64 * 1024];
+        let mut sig =
+            Signature::with_options(Cursor::new(&data), 16, 4, SignatureType::MD4).unwrap();
+        let mut buf = [0; 32];
+        sig.read_exact(&mut buf).unwrap();
+        let consumed = sig.stats().in_bytes;
+        assert!(consumed < data.len() as u64);
+        let input = sig.into_inner_seek().unwrap();
+        assert_eq!(input.position(), consumed);
+    }
+
+    #[test]
+    fn delta() {
+        let sig = data_signature();
+        let input = Cursor::new(DATA2);
+        let mut job = Delta::new(input, &mut Cursor::new(sig)).unwrap();
+        let mut delta = Vec::new();
+        let read = job.read_to_end(&mut delta).unwrap();
+        assert_eq!(read, delta.len());
+        assert_eq!(delta, data2_delta());
+        job.into_inner();
+    }
+
+    #[test]
+    fn delta_reuse_signature() {
+        let sig = data_signature();
+        let sig_len = sig.len() as u64;
+        let mut sig = Cursor::new(sig);
+        let mut first = Vec::new();
+        Delta::new(Cursor::new(DATA2), &mut sig)
+            .unwrap()
+            .read_to_end(&mut first)
+            .unwrap();
+        assert_eq!(sig.position(), sig_len);
+
+        sig.set_position(0);
+        let mut second = Vec::new();
+        Delta::new(Cursor::new(DATA2), &mut sig)
+            .unwrap()
+            .read_to_end(&mut second)
+            .unwrap();
+        assert_eq!(first, second);
+    }
+
+    #[test]
+    fn delta_from_raw_signature() {
+        let mut sig = ptr::null_mut();
+        unsafe {
+            let job = raw::rs_loadsig_begin(&mut sig);
+            let mut job = JobDriver::new(Cursor::new(data_signature()), Job::new(job));
+            job.consume_input().unwrap();
+        }
+        let mut job = unsafe { Delta::from_raw_signature(Cursor::new(DATA2), sig) }.unwrap();
+        let mut delta = Vec::new();
+        job.read_to_end(&mut delta).unwrap();
+        assert_eq!(delta, data2_delta());
+
+        let res = unsafe { Delta::from_raw_signature(Cursor::new(DATA2), ptr::null_mut()) };
+        assert!(res.is_err());
+    }
+
+    // A reader returning a few bytes at a time.
+    struct TrickleReader<T> {
+        inner: T,
+        max_len: usize,
+    }
+
+    impl<T: Read> Read for TrickleReader<T> {
+        fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+            let len = buf.len().min(self.max_len);
+            self.inner.read(&mut buf[..len])
+        }
+    }
+
+    #[test]
+    fn delta_fragmented_signature() {
+        let data = (0..200_000).map(|i| (i % 251) as u8).collect::<Vec<_>>();
+        let mut new = data.clone();
+        new[100_000..100_100].copy_from_slice(&[0; 100]);
+        let mut sig = Vec::new();
+        Signature::with_options(&data[..], 32, 8, SignatureType::Blake2)
+            .unwrap()
+            .read_to_end(&mut sig)
+            .unwrap();
+        // 6250 blocks, spanning many buffers
+        assert!(sig.len() > 10 * job::SIG_OUT_BUF_LEN);
+
+        let mut fragmented = TrickleReader {
+            inner: &sig[..],
+            max_len: 3,
+        };
+        let mut delta = Vec::new();
+        Delta::new(&new[..], &mut fragmented)
+            .unwrap()
+            .read_to_end(&mut delta)
+            .unwrap();
+        let mut expected = Vec::new();
+        Delta::new(&new[..], &mut &sig[..])
+            .unwrap()
+            .read_to_end(&mut expected)
+            .unwrap();
+        assert_eq!(delta, expected);
+
+        let mut patched = Vec::new();
+        Patch::new(Cursor::new(&data), &delta[..])
+            .unwrap()
+            .read_to_end(&mut patched)
+            .unwrap();
+        assert_eq!(patched, new);
+    }
+
+    #[derive(Default)]
+    struct CountingMetrics {
+        starts: Arc<AtomicUsize>,
+        iterations: Arc<AtomicUsize>,
+        dones: Arc<AtomicUsize>,
+        out_bytes: Arc<AtomicUsize>,
+    }
+
+    impl Metrics for CountingMetrics {
+        fn on_start(&mut self, op: OpKind) {
+            assert_eq!(op, OpKind::Delta);
+            self.starts.fetch_add(1, Ordering::SeqCst);
+        }
+
+        fn on_iteration(&mut self) {
+            self.iterations.fetch_add(1, Ordering::SeqCst);
+        }
+
+        fn on_done(&mut self, stats: &Stats) {
+            self.dones.fetch_add(1, Ordering::SeqCst);
+            self.out_bytes
+                .store(stats.out_bytes as usize, Ordering::SeqCst);
+        }
+    }
+
+    #[test]
+    fn delta_metrics() {
+        let data = (0..100_000).map(|i| (i % 251) as u8).collect::<Vec<_>>();
+        let mut sig = Vec::new();
+        Signature::with_options(&data[..], 512, 8, SignatureType::Blake2)
+            .unwrap()
+            .read_to_end(&mut sig)
+            .unwrap();
+
+        let metrics = CountingMetrics::default();
+        let (starts, iterations, dones, out_bytes) = (
+            metrics.starts.clone(),
+            metrics.iterations.clone(),
+            metrics.dones.clone(),
+            metrics.out_bytes.clone(),
+        );
+        let mut delta = Delta::new(&data[..], &mut &sig[..])
+            .unwrap()
+            .with_metrics(metrics);
+        assert_eq!(starts.load(Ordering::SeqCst), 0);
+        let mut out = Vec::new();
+        delta.read_to_end(&mut out).unwrap();
+        // reading past the end does not report the job again
+        assert_eq!(delta.read(&mut [0; 16]).unwrap(), 0);
+        assert_eq!(starts.load(Ordering::SeqCst), 1);
+        assert!(iterations.load(Ordering::SeqCst) > 0);
+        assert_eq!(dones.load(Ordering::SeqCst), 1);
+        assert_eq!(out_bytes.load(Ordering::SeqCst), out.len());
+    }
+
+    // A reader recording whether it has been read.
+    struct TouchedReader<T> {
+        inner: T,
+        touched: Rc<Cell<bool>>,
+    }
+
+    impl<T: Read> Read for TouchedReader<T> {
+        fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+            self.touched.set(true);
+            self.inner.read(buf)
+        }
+    }
+
+    #[test]
+    fn delta_lazy() {
+        let touched = Rc::new(Cell::new(false));
+        let sig = TouchedReader {
+            inner: Cursor::new(data_signature()),
+            touched: touched.clone(),
+        };
+        let mut delta = LazyDelta::new(DATA2.as_bytes(), sig);
+        assert!(!touched.get());
+        assert!(!delta.is_loaded());
+        let mut computed = Vec::new();
+        delta.read_to_end(&mut computed).unwrap();
+        assert!(touched.get());
+        assert!(delta.is_loaded());
+        assert_eq!(computed, data2_delta());
+
+        // errors are reported on first read
+        let not_sig = data2_delta();
+        let mut delta = LazyDelta::new(DATA2.as_bytes(), &not_sig[..]);
+        assert!(delta.read(&mut [0; 16]).is_err());
+        assert!(delta.read(&mut [0; 16]).is_err());
+        assert!(!delta.is_loaded());
+    }
+
+    #[test]
+    fn delta_with_limit() {
+        let data = (0..100_000).map(|i| (i % 251) as u8).collect::<Vec<_>>();
+        let mut sig = Vec::new();
+        Signature::with_options(&data[..], 64, 16, SignatureType::Blake2)
+            .unwrap()
+            .read_to_end(&mut sig)
+            .unwrap();
+        // 1563 blocks of 20 bytes each
+        let sig_mem = sig.len() - SignatureInfo::HEADER_LEN;
+        match Delta::new_with_limit(&data[..], &mut &sig[..], 1024) {
+            Err(Error::Mem) => (),
+            _ => panic!("expected an out of memory error"),
+        }
+        match Delta::new_with_limit(&data[..], &mut &sig[..], sig_mem - 1) {
+            Err(Error::Mem) => (),
+            _ => panic!("expected an out of memory error"),
+        }
+
+        let mut delta = Vec::new();
+        Delta::new_with_limit(&data[..], &mut &sig[..], sig_mem)
+            .unwrap()
+            .read_to_end(&mut delta)
+            .unwrap();
+        let mut expected = Vec::new();
+        Delta::new(&data[..], &mut &sig[..])
+            .unwrap()
+            .read_to_end(&mut expected)
+            .unwrap();
+        assert_eq!(delta, expected);
+    }
+
+    #[test]
+    fn delta_bounded_base() {
+        let mut state = 0x2545_f491u32;
+        let data = (0..20_000)
+            .map(|_| {
+                state ^= state << 13;
+                state ^= state >> 17;
+                state ^= state << 5;
+                state as u8
+            })
+            .collect::<Vec<_>>();
+        let truncated = &data[..10_000];
+        let mut sig = Vec::new();
+        Signature::with_options(&data[..], 64, 16, SignatureType::Blake2)
+            .unwrap()
+            .read_to_end(&mut sig)
+            .unwrap();
+
+        let mut delta = Vec::new();
+        Delta::new(&data[..], &mut &sig[..])
+            .unwrap()
+            .read_to_end(&mut delta)
+            .unwrap();
+        let mut out = Vec::new();
+        let res = Patch::new(Cursor::new(truncated), &delta[..])
+            .and_then(|mut patch| Ok(patch.read_to_end(&mut out)?));
+        assert!(res.is_err());
+
+        let mut delta = Vec::new();
+        Delta::new_bounded_base(&data[..], &mut &sig[..], truncated.len() as u64)
+            .unwrap()
+            .read_to_end(&mut delta)
+            .unwrap();
+        let mut out = Vec::new();
+        Patch::new(Cursor::new(truncated), &delta[..])
+            .unwrap()
+            .read_to_end(&mut out)
+            .unwrap();
+        assert_eq!(out, data);
+
+        // a header with a zero block length
+        let sig = [
+            0x72, 0x73, 0x01, 0x36, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x05,
+        ];
+        match Delta::new_bounded_base(&data[..], &mut &sig[..], 1000) {
+            Err(Error::Corrupt) => (),
+            _ => panic!("expected corrupt error"),
+        }
+    }
+
+    #[test]
+    fn delta_strict() {
+        match Delta::new_strict(Cursor::new(DATA2), &mut Cursor::new(data_signature())) {
+            Err(Error::WeakChecksum) => (),
+            _ => panic!("expected weak checksum error"),
+        }
+        // the same signature, with the RabinKarp rolling checksum
+        let mut rk_md4 = data_signature();
+        rk_md4[3] = 0x46;
+        match Delta::new_strict(Cursor::new(DATA2), &mut &rk_md4[..]) {
+            Err(Error::WeakChecksum) => (),
+            _ => panic!("expected weak checksum error"),
+        }
+
+        let mut sig = Vec::new();
+        Signature::with_options(Cursor::new(DATA), 10, 5, SignatureType::Blake2)
+            .unwrap()
+            .read_to_end(&mut sig)
+            .unwrap();
+        let mut strict = Vec::new();
+        Delta::new_strict(Cursor::new(DATA2), &mut &sig[..])
+            .unwrap()
+            .read_to_end(&mut strict)
+            .unwrap();
+        let mut plain = Vec::new();
+        Delta::new(Cursor::new(DATA2), &mut &sig[..])
+            .unwrap()
+            .read_to_end(&mut plain)
+            .unwrap();
+        assert_eq!(strict, plain);
+    }
+
+    #[test]
+    fn patch() {
+        let base = Cursor::new(DATA);
+        let delta = data2_delta();
+        let delta = Cursor::new(delta);
+        let mut patch = Patch::new(base, delta).unwrap();
+        let mut computed_new = String::new();
+        patch.read_to_string(&mut computed_new).unwrap();
+        assert_eq!(computed_new, DATA2);
+        patch.into_inner();
+    }
+
+    // a delta with three sequential copy commands of 5 bytes each
+    fn sequential_copies_delta() -> Vec<u8> {
+        vec![
+            0x72, 0x73, 0x02, 0x36, 0x45, 0x00, 0x05, 0x45, 0x05, 0x05, 0x45, 0x0a, 0x05, 0x00,
+        ]
+    }
+
+    struct SeekCounter<T> {
+        inner: T,
+        seeks: usize,
+    }
+
+    impl<T: Read> Read for SeekCounter<T> {
+        fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+            self.inner.read(buf)
+        }
+    }
+
+    impl<T: Seek> Seek for SeekCounter<T> {
+        fn seek(&mut self, pos: io::SeekFrom) -> io::Result<u64> {
+            self.seeks += 1;
+            self.inner.seek(pos)
+        }
+    }
+
+    #[test]
+    fn patch_sequential_copies() {
+        let base = SeekCounter {
+            inner: Cursor::new(DATA),
+            seeks: 0,
+        };
+        let delta = Cursor::new(sequential_copies_delta());
+        let mut patch = Patch::new(base, delta).unwrap();
+        let mut computed_new = String::new();
+        patch.read_to_string(&mut computed_new).unwrap();
+        assert_eq!(computed_new, &DATA[..15]);
+        let (base, _) = patch.into_inner();
+        assert_eq!(base.seeks, 1);
+    }
+
+    struct PanickingBase;
+
+    impl Read for PanickingBase {
+        fn read(&mut self, _buf: &mut [u8]) -> io::Result<usize> {
+            panic!("base read failure");
+        }
+    }
+
+    impl Seek for PanickingBase {
+        fn seek(&mut self, _pos: io::SeekFrom) -> io::Result<u64> {
+            Ok(0)
+        }
+    }
+
+    #[test]
+    fn patch_panicking_base() {
+        let delta = Cursor::new(data2_delta());
+        let mut patch = Patch::new(PanickingBase, delta).unwrap();
+        let mut computed_new = Vec::new();
+        assert!(patch.read_to_end(&mut computed_new).is_err());
+    }
+
+    #[test]
+    fn buf_read_matches_read() {
+        let data = (0..100_000).map(|i| (i % 253) as u8).collect::<Vec<_>>();
+        let mut new = data.clone();
+        new[50_000..50_100].copy_from_slice(&[0; 100]);
+
+        let mut sig = Vec::new();
+        Signature::with_options(&data[..], 512, 8, SignatureType::Blake2)
+            .unwrap()
+            .read_to_end(&mut sig)
+            .unwrap();
+        let mut buf_sig = Vec::new();
+        Signature::with_buf_read(Cursor::new(&data), 512, 8, SignatureType::Blake2)
+            .unwrap()
+            .read_to_end(&mut buf_sig)
+            .unwrap();
+        assert_eq!(sig, buf_sig);
 
-#[cfg(test)]
-mod test {
-    use super::*;
-    use std::io::{Cursor, Read};
-    use std::thread;
+        let mut delta = Vec::new();
+        Delta::new(&new[..], &mut &sig[..])
+            .unwrap()
+            .read_to_end(&mut delta)
+            .unwrap();
+        let mut buf_delta = Vec::new();
+        Delta::with_buf_read(Cursor::new(&new), &mut &sig[..])
+            .unwrap()
+            .read_to_end(&mut buf_delta)
+            .unwrap();
+        assert_eq!(delta, buf_delta);
 
-    const DATA: &'static str = "this is a string to be tested";
-    const DATA2: &'static str = "this is another string to be tested";
+        let mut patched = Vec::new();
+        Patch::with_buf_read(Cursor::new(&data), Cursor::new(&delta))
+            .unwrap()
+            .read_to_end(&mut patched)
+            .unwrap();
+        assert_eq!(patched, new);
+    }
 
-    // generated with `rdiff signature -b 10 -S 5 data data.sig`
-    fn data_signature() -> Vec<u8> {
-        vec![
-            0x72, 0x73, 0x01, 0x36, 0x00, 0x00, 0x00, 0x0a, 0x00, 0x00, 0x00, 0x05, 0x1b, 0x21,
-            0x04, 0x8b, 0xad, 0x3c, 0xbd, 0x19, 0x09, 0x1d, 0x1b, 0x04, 0xf0, 0x9d, 0x1f, 0x64,
-            0x31, 0xde, 0x15, 0xf4, 0x04, 0x87, 0x60, 0x96, 0x19, 0x50, 0x39,
-        ]
+    #[test]
+    fn patch_max_output() {
+        let base = vec![7; 1000];
+        // copy the whole basis file 100 times
+        let mut delta = vec![0x72, 0x73, 0x02, 0x36];
+        for _ in 0..100 {
+            delta.extend_from_slice(&[0x46, 0x00, 0x03, 0xe8]);
+        }
+        delta.push(0x00);
+
+        let mut patch = Patch::with_max_output(Cursor::new(&base), &delta[..], 10_000).unwrap();
+        let mut out = Vec::new();
+        let err = patch.read_to_end(&mut out).unwrap_err();
+        assert_eq!(err.kind(), io::ErrorKind::InvalidData);
+        assert!(out.len() <= 10_000);
+        assert!(patch.stats().out_bytes <= 10_001);
+        // the job stays halted
+        assert!(patch.read(&mut [0; 16]).is_err());
+
+        let mut patch = Patch::with_max_output(Cursor::new(&base), &delta[..], 100_000).unwrap();
+        let mut out = Vec::new();
+        patch.read_to_end(&mut out).unwrap();
+        assert_eq!(out.len(), 100_000);
     }
 
-    // generated with `rdiff delta data.sig data2 data2.delta`
-    fn data2_delta() -> Vec<u8> {
-        vec![
-            0x72, 0x73, 0x02, 0x36, 0x10, 0x74, 0x68, 0x69, 0x73, 0x20, 0x69, 0x73, 0x20, 0x61,
-            0x6e, 0x6f, 0x74, 0x68, 0x65, 0x72, 0x20, 0x45, 0x0a, 0x13, 0x00,
-        ]
+    #[test]
+    fn patch_lazy() {
+        // a delta with the literal "abc" only
+        let delta = vec![0x72, 0x73, 0x02, 0x36, 0x03, 0x61, 0x62, 0x63, 0x00];
+        let open = || -> io::Result<Cursor<&str>> { panic!("basis file opened") };
+        let mut patch = Patch::new_lazy(open, Cursor::new(delta)).unwrap();
+        let mut computed_new = String::new();
+        patch.read_to_string(&mut computed_new).unwrap();
+        assert_eq!(computed_new, "abc");
+        let (base, _) = patch.into_inner();
+        assert!(!base.is_open());
+
+        let open = || Ok(Cursor::new(DATA));
+        let mut patch = Patch::new_lazy(open, Cursor::new(data2_delta())).unwrap();
+        let mut computed_new = String::new();
+        patch.read_to_string(&mut computed_new).unwrap();
+        assert_eq!(computed_new, DATA2);
+        let (base, _) = patch.into_inner();
+        assert!(base.is_open());
+    }
+
+    // A base failing the first reads with transient errors.
+    struct FlakyBase {
+        inner: Cursor<&'static str>,
+        failures: usize,
+    }
+
+    impl Read for FlakyBase {
+        fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+            if self.failures > 0 {
+                self.failures -= 1;
+                return Err(io::Error::from(io::ErrorKind::WouldBlock));
+            }
+            self.inner.read(buf)
+        }
+    }
+
+    impl Seek for FlakyBase {
+        fn seek(&mut self, pos: io::SeekFrom) -> io::Result<u64> {
+            self.inner.seek(pos)
+        }
     }
 
     #[test]
-    fn signature() {
-        let cursor = Cursor::new(DATA);
-        let mut sig = Signature::with_options(cursor, 10, 5, SignatureType::MD4).unwrap();
-        let mut signature = Vec::new();
-        let read = sig.read_to_end(&mut signature).unwrap();
-        assert_eq!(read, signature.len());
-        assert_eq!(signature, data_signature());
-        sig.into_inner();
+    fn patch_retry() {
+        let flaky = |failures| FlakyBase {
+            inner: Cursor::new(DATA),
+            failures,
+        };
+        let mut patch = Patch::new_with_retry(flaky(3), Cursor::new(data2_delta()), 3).unwrap();
+        let mut computed_new = String::new();
+        patch.read_to_string(&mut computed_new).unwrap();
+        assert_eq!(computed_new, DATA2);
+
+        let mut patch = Patch::new_with_retry(flaky(4), Cursor::new(data2_delta()), 3).unwrap();
+        assert!(patch.read_to_string(&mut String::new()).is_err());
+        let mut patch = Patch::new(flaky(1), Cursor::new(data2_delta())).unwrap();
+        assert!(patch.read_to_string(&mut String::new()).is_err());
     }
 
+    #[cfg(feature = "output-trace")]
     #[test]
-    fn delta() {
-        let sig = data_signature();
-        let input = Cursor::new(DATA2);
-        let mut job = Delta::new(input, &mut Cursor::new(sig)).unwrap();
-        let mut delta = Vec::new();
-        let read = job.read_to_end(&mut delta).unwrap();
-        assert_eq!(read, delta.len());
-        assert_eq!(delta, data2_delta());
-        job.into_inner();
+    fn signature_trace() {
+        let data = (0..100_000).map(|i| (i % 251) as u8).collect::<Vec<_>>();
+        let chunks = Arc::new(Mutex::new(Vec::new()));
+        let traced = chunks.clone();
+        let mut sig = Signature::with_options(&data[..], 64, 8, SignatureType::Blake2)
+            .unwrap()
+            .with_trace(move |chunk| traced.lock().unwrap().push(chunk.to_vec()));
+        let mut out = Vec::new();
+        let mut buf = [0; 1000];
+        loop {
+            match sig.read(&mut buf).unwrap() {
+                0 => break,
+                read => out.extend_from_slice(&buf[..read]),
+            }
+        }
+        let chunks = chunks.lock().unwrap();
+        assert!(chunks.len() > 1);
+        assert_eq!(chunks.concat(), out);
+
+        let mut expected = Vec::new();
+        Signature::with_options(&data[..], 64, 8, SignatureType::Blake2)
+            .unwrap()
+            .read_to_end(&mut expected)
+            .unwrap();
+        assert_eq!(out, expected);
     }
 
+    #[cfg(feature = "copy-hook")]
     #[test]
-    fn patch() {
-        let base = Cursor::new(DATA);
-        let delta = data2_delta();
-        let delta = Cursor::new(delta);
-        let mut patch = Patch::new(base, delta).unwrap();
+    fn patch_on_copy() {
+        let copies = Rc::new(RefCell::new(Vec::new()));
+        let mut patch = Patch::new(Cursor::new(DATA), Cursor::new(data2_delta())).unwrap();
+        let recorded = copies.clone();
+        patch.on_copy(move |pos, len| recorded.borrow_mut().push((pos, len)));
         let mut computed_new = String::new();
         patch.read_to_string(&mut computed_new).unwrap();
         assert_eq!(computed_new, DATA2);
-        patch.into_inner();
+        assert_eq!(*copies.borrow(), vec![(10, 19)]);
+    }
+
+    #[test]
+    fn patch_output_signature() {
+        let mut patch = Patch::with_output_signature(
+            Cursor::new(DATA),
+            Cursor::new(data2_delta()),
+            10,
+            5,
+            SignatureType::MD4,
+        )
+        .unwrap();
+        let mut computed_new = String::new();
+        patch.read_to_string(&mut computed_new).unwrap();
+        assert_eq!(computed_new, DATA2);
+        let (_, sig) = patch.finish().unwrap();
+
+        let mut expected = Vec::new();
+        Signature::with_options(DATA2.as_bytes(), 10, 5, SignatureType::MD4)
+            .unwrap()
+            .read_to_end(&mut expected)
+            .unwrap();
+        assert_eq!(sig, expected);
+    }
+
+    // A non-blocking reader, which has no data available before every read of a few bytes.
+    struct NonBlockingReader<T> {
+        inner: T,
+        ready: bool,
+    }
+
+    impl<T: Read> Read for NonBlockingReader<T> {
+        fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+            if !self.ready {
+                self.ready = true;
+                return Err(io::ErrorKind::WouldBlock.into());
+            }
+            self.ready = false;
+            let len = buf.len().min(3);
+            self.inner.read(&mut buf[..len])
+        }
+    }
+
+    #[test]
+    fn signature_state() {
+        let input = NonBlockingReader {
+            inner: DATA.as_bytes(),
+            ready: false,
+        };
+        let mut sig = Signature::with_options(input, 10, 5, SignatureType::MD4).unwrap();
+        assert_eq!(sig.state(), JobState::Running);
+        let mut out = Vec::new();
+        let mut buf = [0; 4];
+        let mut states = Vec::new();
+        loop {
+            match sig.read(&mut buf) {
+                Ok(0) => break,
+                Ok(read) => out.extend_from_slice(&buf[..read]),
+                Err(ref e) if e.kind() == io::ErrorKind::WouldBlock => (),
+                Err(e) => panic!("unexpected error: {}", e),
+            }
+            states.push(sig.state());
+        }
+        assert_eq!(out, data_signature());
+        assert!(states.contains(&JobState::NeedMoreInput));
+        assert!(states.contains(&JobState::Running));
+        assert_eq!(sig.state(), JobState::Done);
+
+        let input = FailingReader {
+            inner: DATA.as_bytes(),
+            left: 0,
+        };
+        let mut sig = Signature::new(input).unwrap();
+        assert!(sig.read(&mut buf).is_err());
+        assert_eq!(sig.state(), JobState::Errored);
+    }
+
+    #[test]
+    fn patch_chunk_fn() {
+        let chunks = DATA
+            .as_bytes()
+            .chunks(4)
+            .map(|c| c.to_vec())
+            .collect::<Vec<_>>();
+        let chunk = |index: u64| -> io::Result<Vec<u8>> {
+            Ok(chunks.get(index as usize).cloned().unwrap_or_default())
+        };
+        let mut fetched = Vec::new();
+        let fetch = |index| {
+            fetched.push(index);
+            chunk(index)
+        };
+        let mut patch = Patch::from_chunk_fn(Cursor::new(data2_delta()), fetch, 4).unwrap();
+        let mut computed_new = String::new();
+        patch.read_to_string(&mut computed_new).unwrap();
+        assert_eq!(computed_new, DATA2);
+        drop(patch);
+        // the copy of 19 bytes from offset 10 spans the chunks from 2 to 7, fetched once each
+        assert_eq!(fetched, vec![2, 3, 4, 5, 6, 7]);
+
+        // a copy beyond the last chunk
+        let delta = [0x72, 0x73, 0x02, 0x36, 0x45, 0x1c, 0x04, 0x00];
+        let mut patch = Patch::from_chunk_fn(&delta[..], chunk, 4).unwrap();
+        assert!(patch.read_to_end(&mut Vec::new()).is_err());
+    }
+
+    #[test]
+    fn patch_nonblocking_delta() {
+        let delta = NonBlockingReader {
+            inner: Cursor::new(data2_delta()),
+            ready: false,
+        };
+        let mut patch = Patch::new(Cursor::new(DATA), delta).unwrap();
+        let mut computed_new = Vec::new();
+        let mut buf = [0; 4];
+        let mut blocked = 0;
+        loop {
+            match patch.read(&mut buf) {
+                Ok(0) => break,
+                Ok(read) => computed_new.extend_from_slice(&buf[..read]),
+                Err(ref e) if e.kind() == io::ErrorKind::WouldBlock => blocked += 1,
+                Err(e) => panic!("unexpected error: {}", e),
+            }
+        }
+        assert_eq!(computed_new, DATA2.as_bytes());
+        assert!(blocked > 1);
+    }
+
+    #[test]
+    fn patch_recorded_copies() {
+        let mut patch = Patch::new(Cursor::new(DATA), Cursor::new(data2_delta())).unwrap();
+        patch.record_copies();
+        let mut computed_new = String::new();
+        patch.read_to_string(&mut computed_new).unwrap();
+        assert_eq!(computed_new, DATA2);
+        let (base, copies) = patch.into_parts();
+        assert_eq!(base.into_inner(), DATA);
+        assert_eq!(
+            copies,
+            vec![Command::Copy {
+                offset: 10,
+                len: 19
+            }]
+        );
+
+        let patch = Patch::new(Cursor::new(DATA), Cursor::new(data2_delta())).unwrap();
+        assert_eq!(patch.into_parts().1, vec![]);
+    }
+
+    struct ReadCounter<T> {
+        inner: T,
+        reads: usize,
+    }
+
+    impl<T: Read> Read for ReadCounter<T> {
+        fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+            self.reads += 1;
+            self.inner.read(buf)
+        }
+    }
+
+    impl<T: Seek> Seek for ReadCounter<T> {
+        fn seek(&mut self, pos: io::SeekFrom) -> io::Result<u64> {
+            self.inner.seek(pos)
+        }
+    }
+
+    #[test]
+    fn patch_caching_base() {
+        // a delta copying the first 10 bytes twice
+        let delta = vec![
+            0x72, 0x73, 0x02, 0x36, 0x45, 0x00, 0x0a, 0x45, 0x00, 0x0a, 0x00,
+        ];
+        let inner = ReadCounter {
+            inner: Cursor::new(DATA),
+            reads: 0,
+        };
+        let mut patch = Patch::new(CachingBase::new(inner, 1024), Cursor::new(delta)).unwrap();
+        let mut computed_new = String::new();
+        patch.read_to_string(&mut computed_new).unwrap();
+        assert_eq!(computed_new, DATA[..10].repeat(2));
+        let (base, _) = patch.into_inner();
+        assert_eq!(base.hits(), 1);
+        assert_eq!(base.misses(), 1);
+        assert_eq!(base.into_inner().reads, 1);
+
+        // regions larger than the cache are not kept
+        let mut base = CachingBase::new(Cursor::new(DATA), 4);
+        let mut buf = [0; 8];
+        for _ in 0..2 {
+            base.seek(io::SeekFrom::Start(2)).unwrap();
+            base.read_exact(&mut buf).unwrap();
+            assert_eq!(&buf, b"is is a ");
+        }
+        assert_eq!(base.hits(), 0);
+        assert_eq!(base.misses(), 2);
+    }
+
+    #[test]
+    fn patch_copy_offsets() {
+        let mut cb_data = Box::new(CopySource {
+            base: Rc::new(RefCell::new(Cursor::new(DATA))),
+            retries: 0,
+            #[cfg(feature = "copy-hook")]
+            on_copy: RefCell::new(None),
+            copies: RefCell::new(None),
+        });
+        let opaque: *mut CopySource = &mut *cb_data;
+        let copy = |pos| {
+            let mut buf = [0u8; 4];
+            let mut len = buf.len();
+            let mut ptr = buf.as_mut_ptr() as *mut libc::c_void;
+            let res = patch_copy_cb(opaque as *mut libc::c_void, pos, &mut len, &mut ptr);
+            (res, buf[..len].to_vec())
+        };
+        assert_eq!(copy(10), (raw::RS_DONE, b"stri".to_vec()));
+        assert_eq!(copy(-1).0, raw::RS_IO_ERROR);
+        assert_eq!(copy(raw::rs_long_t::MIN).0, raw::RS_IO_ERROR);
+        // far beyond the end of the base, but still a valid offset
+        assert_eq!(copy(raw::rs_long_t::MAX).0, raw::RS_INPUT_ENDED);
     }
 
     #[test]
@@ -541,6 +3786,383 @@ mod test {
         assert_eq!(computed_new, DATA2);
     }
 
+    #[test]
+    fn stats() {
+        let sig = data_signature();
+        let input = Cursor::new(DATA2);
+        let mut job = Delta::new(input, &mut Cursor::new(sig)).unwrap();
+        let mut delta = Vec::new();
+        job.read_to_end(&mut delta).unwrap();
+        let stats = job.stats();
+        assert_eq!(stats.lit_cmds, 1);
+        assert_eq!(stats.lit_bytes, 16);
+        assert_eq!(stats.copy_cmds, 1);
+        assert_eq!(stats.copy_bytes, 19);
+        assert_eq!(stats.in_bytes, DATA2.len() as u64);
+        assert_eq!(stats.out_bytes, delta.len() as u64);
+    }
+
+    #[test]
+    fn stats_ratios() {
+        let all_literal = Stats {
+            lit_bytes: 100,
+            out_bytes: 106,
+            ..Stats::default()
+        };
+        assert_eq!(all_literal.match_ratio(), 0.0);
+        assert_eq!(all_literal.compression_ratio(), 1.06);
+
+        let all_copy = Stats {
+            copy_bytes: 1000,
+            out_bytes: 10,
+            ..Stats::default()
+        };
+        assert_eq!(all_copy.match_ratio(), 1.0);
+        assert_eq!(all_copy.compression_ratio(), 0.01);
+
+        let mixed = Stats {
+            lit_bytes: 25,
+            copy_bytes: 75,
+            out_bytes: 40,
+            ..Stats::default()
+        };
+        assert_eq!(mixed.match_ratio(), 0.75);
+        assert_eq!(mixed.compression_ratio(), 0.4);
+
+        assert_eq!(Stats::default().match_ratio(), 0.0);
+        assert_eq!(Stats::default().compression_ratio(), 0.0);
+    }
+
+    #[test]
+    fn signature_type_magic() {
+        let rollings = [RollingKind::Rollsum, RollingKind::RabinKarp];
+        let digests = [DigestKind::MD4, DigestKind::Blake2];
+        let magics = [0x7273_0136, 0x7273_0137, 0x7273_0146, 0x7273_0147];
+        let mut sig_types = Vec::new();
+        for (i, &rolling) in rollings.iter().enumerate() {
+            for (j, &digest) in digests.iter().enumerate() {
+                let sig_type = SignatureType::new(rolling, digest).unwrap();
+                assert_eq!(sig_type.rolling(), rolling);
+                assert_eq!(sig_type.digest(), digest);
+                assert_eq!(sig_type.magic(), magics[i * 2 + j]);
+                sig_types.push(sig_type);
+            }
+        }
+        assert_eq!(sig_types[0], SignatureType::MD4);
+        assert_eq!(sig_types[1], SignatureType::Blake2);
+        // ordered by magic number
+        let mut sorted = sig_types.clone();
+        sorted.sort();
+        assert_eq!(sorted, sig_types);
+
+        for sig_type in &sig_types {
+            let name = sig_type.to_string();
+            assert_eq!(name.parse::<SignatureType>().unwrap(), *sig_type);
+            assert_eq!(
+                name.to_uppercase().parse::<SignatureType>().unwrap(),
+                *sig_type
+            );
+        }
+        assert_eq!(sig_types[3].to_string(), "rk-blake2");
+        match "sha1".parse::<SignatureType>() {
+            Err(ref e) if e.kind() == ErrorKind::Io => (),
+            _ => panic!("expected an invalid input error"),
+        }
+
+        // no signature format has this magic number
+        let mut sig = data_signature();
+        sig[3] = 0x38;
+        match SignatureInfo::parse(&sig) {
+            Err(Error::BadMagic) => (),
+            _ => panic!("expected bad magic error"),
+        }
+    }
+
+    #[test]
+    fn signature_type_supported() {
+        assert!(SignatureType::MD4.is_supported());
+        let blake2 = SignatureType::Blake2.is_supported();
+        // the probe is cached
+        assert_eq!(SignatureType::Blake2.is_supported(), blake2);
+    }
+
+    #[test]
+    fn signature_info_blocks() {
+        let info = SignatureInfo::parse(&data_signature()).unwrap();
+        assert_eq!(info.block_count(0), 0);
+        assert_eq!(info.block_count(1), 1);
+        assert_eq!(info.block_count(10), 1);
+        assert_eq!(info.block_count(11), 2);
+        assert_eq!(info.block_count(DATA.len() as u64), 3);
+        assert_eq!(info.block_count(u64::MAX), u64::MAX / 10 + 1);
+        let info = SignatureInfo {
+            block_len: 0,
+            ..info
+        };
+        assert_eq!(info.block_count(100), 0);
+
+        let sig = data_signature();
+        assert_eq!(whole::count_blocks(&sig[..]).unwrap(), 3);
+        let header = &sig[..SignatureInfo::HEADER_LEN];
+        assert_eq!(whole::count_blocks(header).unwrap(), 0);
+        assert!(whole::count_blocks(&sig[..sig.len() - 1]).is_err());
+        assert!(whole::count_blocks(&data2_delta()[..]).is_err());
+    }
+
+    #[test]
+    fn signature_info_memory() {
+        let info = SignatureInfo {
+            sig_type: SignatureType::Blake2,
+            block_len: 2048,
+            strong_len: 32,
+        };
+        let header_len = SignatureInfo::HEADER_LEN as u64;
+        let empty = info.estimated_hash_table_bytes(header_len);
+        assert!(empty < 64);
+        assert_eq!(info.estimated_hash_table_bytes(0), empty);
+        for &blocks in &[1u64, 100, 10_000, 1 << 20] {
+            let sig_len = info.sig_type.signature_len(2048, 32, blocks * 2048);
+            let estimate = info.estimated_hash_table_bytes(sig_len);
+            // at least the block entries, at most the entries plus a half empty table
+            assert!(estimate >= blocks * 36);
+            assert!(estimate <= blocks * 80 + 64);
+        }
+
+        let info = SignatureInfo::parse(&data_signature()).unwrap();
+        let estimate = info.estimated_hash_table_bytes(data_signature().len() as u64);
+        assert!(estimate >= 3 * 9);
+        assert!(estimate < 1024);
+    }
+
+    #[test]
+    fn signature_info() {
+        let info = SignatureInfo::parse(&data_signature()).unwrap();
+        assert_eq!(
+            info,
+            SignatureInfo {
+                sig_type: SignatureType::MD4,
+                block_len: 10,
+                strong_len: 5,
+            }
+        );
+        assert!(SignatureInfo::parse(&data_signature()[..8]).is_err());
+        match SignatureInfo::parse(&data2_delta()) {
+            Err(Error::BadMagic) => (),
+            _ => panic!("expected bad magic error"),
+        }
+
+        // RabinKarp signature
+        let mut sig = data_signature();
+        sig[3] = 0x46;
+        let sig_type = SignatureInfo::parse(&sig).unwrap().sig_type;
+        assert_eq!(sig_type.rolling(), RollingKind::RabinKarp);
+        assert_eq!(sig_type.digest(), DigestKind::MD4);
+        sig[3] = 0x99;
+        match SignatureInfo::parse(&sig) {
+            Err(Error::BadMagic) => (),
+            _ => panic!("expected bad magic error"),
+        }
+    }
+
+    #[test]
+    fn signature_info_copy() {
+        let info = SignatureInfo::parse(&data_signature()).unwrap();
+        let copied = info;
+        assert_eq!(copied, info);
+        assert_ne!(
+            SignatureInfo {
+                block_len: 20,
+                ..info
+            },
+            info
+        );
+    }
+
+    #[test]
+    fn signature_info_key() {
+        use std::collections::HashSet;
+
+        let info = SignatureInfo::parse(&data_signature()).unwrap();
+        let blake2 = SignatureInfo {
+            sig_type: SignatureType::Blake2,
+            ..info
+        };
+        let mut set = HashSet::new();
+        assert!(set.insert(info));
+        assert!(set.insert(blake2));
+        assert!(!set.insert(info));
+        assert!(set.insert(SignatureInfo {
+            block_len: 20,
+            ..info
+        }));
+        assert_eq!(set.len(), 3);
+
+        assert!(SignatureType::MD4 < SignatureType::Blake2);
+        let mut sorted: Vec<_> = set.into_iter().collect();
+        sorted.sort();
+        assert_eq!(sorted[0], info);
+        assert_eq!(sorted[2], blake2);
+    }
+
+    #[test]
+    fn block_len_helpers() {
+        assert_eq!(block_len_for_granularity(0, 100), MIN_BLOCK_LEN);
+        assert_eq!(block_len_for_granularity(1000, 0), 1024);
+        assert_eq!(block_len_for_granularity(1 << 20, 1000), 2048);
+        assert_eq!(block_len_for_granularity(1 << 20, 1024), 1024);
+        assert_eq!(block_len_for_granularity(1 << 30, 1000), 2 << 20);
+        assert_eq!(block_len_for_granularity(1 << 20, 1 << 20), MIN_BLOCK_LEN);
+        assert_eq!(block_len_for_granularity(u64::MAX, 1), 1 << 31);
+        for &(file_len, target_blocks) in &[(12_345_678u64, 777), (1 << 40, 100_000)] {
+            let block_len = block_len_for_granularity(file_len, target_blocks);
+            let blocks = div_ceil(file_len, block_len as u64);
+            assert!(blocks <= target_blocks as u64);
+            assert!(blocks > target_blocks as u64 / 2);
+        }
+
+        assert_eq!(block_len_for_resolution(0), MIN_BLOCK_LEN);
+        assert_eq!(block_len_for_resolution(100), MIN_BLOCK_LEN);
+        assert_eq!(block_len_for_resolution(1000), 512);
+        assert_eq!(block_len_for_resolution(4096), 4096);
+        assert_eq!(block_len_for_resolution(5000), 4096);
+    }
+
+    #[test]
+    fn signature_scratch_buffer() {
+        let builder = SignatureBuilder::new().block_len(16).strong_len(8);
+        let mut scratch = Vec::with_capacity(job::SIG_OUT_BUF_LEN);
+        let ptr = scratch.as_ptr();
+        for i in 0..5 {
+            let input = DATA.repeat(i + 1);
+            let mut sig = Signature::new_in(input.as_bytes(), builder, scratch).unwrap();
+            let mut out = Vec::new();
+            sig.read_to_end(&mut out).unwrap();
+            scratch = sig.into_parts().1;
+            assert_eq!(scratch.as_ptr(), ptr);
+
+            let mut expected = Vec::new();
+            builder
+                .build(input.as_bytes())
+                .unwrap()
+                .read_to_end(&mut expected)
+                .unwrap();
+            assert_eq!(out, expected);
+        }
+    }
+
+    #[test]
+    fn signature_default_block_len() {
+        let (sig, info) =
+            Signature::compute(DATA.as_bytes(), SignatureBuilder::new().block_len(0)).unwrap();
+        assert_eq!(info.block_len, raw::RS_DEFAULT_BLOCK_LEN);
+        assert_eq!(
+            SignatureBuilder::new().block_len(0),
+            SignatureBuilder::new()
+        );
+
+        let mut expected = Vec::new();
+        Signature::new(DATA.as_bytes())
+            .unwrap()
+            .read_to_end(&mut expected)
+            .unwrap();
+        assert_eq!(sig, expected);
+        let mut zero = Vec::new();
+        Signature::with_options(DATA.as_bytes(), 0, 0, SignatureType::Blake2)
+            .unwrap()
+            .read_to_end(&mut zero)
+            .unwrap();
+        assert_eq!(zero, expected);
+    }
+
+    #[test]
+    fn signature_block_progress() {
+        let data = vec![7; 10_000];
+        let calls = Arc::new(AtomicUsize::new(0));
+        let last = Arc::new(Mutex::new(None));
+        let mut sig = Signature::with_options(&data[..], 64, 8, SignatureType::Blake2)
+            .unwrap()
+            .with_block_progress(64, Some(data.len() as u64), {
+                let calls = calls.clone();
+                let last = last.clone();
+                move |done, total| {
+                    calls.fetch_add(1, Ordering::SeqCst);
+                    *last.lock().unwrap() = Some((done, total));
+                }
+            });
+        sig.read_to_end(&mut Vec::new()).unwrap();
+        // 156 full blocks, and a partial one
+        assert_eq!(calls.load(Ordering::SeqCst), 157);
+        assert_eq!(*last.lock().unwrap(), Some((157, Some(157))));
+    }
+
+    #[test]
+    fn signature_compute() {
+        let builder = SignatureBuilder::new()
+            .block_len(10)
+            .sig_type(SignatureType::MD4);
+        let (sig, info) = Signature::compute(DATA.as_bytes(), builder).unwrap();
+        assert_eq!(info, SignatureInfo::parse(&sig).unwrap());
+        assert_eq!(
+            info,
+            SignatureInfo {
+                sig_type: SignatureType::MD4,
+                block_len: 10,
+                strong_len: 16,
+            }
+        );
+
+        let delta = Delta::new(DATA2.as_bytes(), &mut &sig[..]).unwrap();
+        let mut patch = Patch::new(Cursor::new(DATA), delta).unwrap();
+        let mut computed_new = String::new();
+        patch.read_to_string(&mut computed_new).unwrap();
+        assert_eq!(computed_new, DATA2);
+    }
+
+    #[test]
+    fn signature_builder() {
+        let builder = SignatureBuilder::new()
+            .block_len(10)
+            .strong_len(5)
+            .sig_type(SignatureType::MD4);
+        assert_ne!(builder, SignatureBuilder::default());
+        let mut signature = Vec::new();
+        builder
+            .build(Cursor::new(DATA))
+            .unwrap()
+            .read_to_end(&mut signature)
+            .unwrap();
+        assert_eq!(signature, data_signature());
+    }
+
+    #[cfg(feature = "serde")]
+    #[test]
+    fn serialize_stats() {
+        let stats = Stats {
+            lit_bytes: 16,
+            copy_bytes: 19,
+            ..Stats::default()
+        };
+        let json = serde_json::to_value(stats).unwrap();
+        assert_eq!(json["lit_bytes"], 16);
+        assert_eq!(json["copy_bytes"], 19);
+        assert_eq!(json["out_bytes"], 0);
+        let back: Stats = serde_json::from_value(json).unwrap();
+        assert_eq!(back, stats);
+
+        let info = SignatureInfo::parse(&data_signature()).unwrap();
+        let json = serde_json::to_value(info).unwrap();
+        assert_eq!(json["sig_type"], "md4");
+        assert_eq!(json["block_len"], 10);
+        assert_eq!(
+            serde_json::to_string(&SignatureType::Blake2).unwrap(),
+            "\"blake2\""
+        );
+        let rk_md4 = SignatureType::new(RollingKind::RabinKarp, DigestKind::MD4).unwrap();
+        let back: SignatureType = serde_json::from_str("\"rk-md4\"").unwrap();
+        assert_eq!(back, rk_md4);
+        assert!(serde_json::from_str::<SignatureType>("\"sha1\"").is_err());
+    }
+
     #[test]
     fn send_sig() {
         let cursor = Cursor::new(DATA);
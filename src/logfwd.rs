@@ -1,4 +1,6 @@
 use libc::c_char;
+#[cfg(any(feature = "log", feature = "tracing"))]
+use std::cmp;
 use std::sync::Once;
 
 use crate::raw;
@@ -16,10 +18,8 @@ pub fn init() {
     }
 }
 
-#[cfg(feature = "log")]
+#[cfg(any(feature = "log", feature = "tracing"))]
 fn init_impl() {
-    use log::LevelFilter;
-
     // trace to our callback
     unsafe {
         raw::rs_trace_to(trace);
@@ -28,21 +28,67 @@ fn init_impl() {
     // determine log level
     // this is useful because if the setted level is not Debug we can optimize librsync log
     // calls
-    let level = match log::max_level() {
-        LevelFilter::Info => raw::RS_LOG_NOTICE,
-        LevelFilter::Debug | LevelFilter::Trace => raw::RS_LOG_DEBUG,
-        _ => raw::RS_LOG_WARNING,
-    };
+    let level = cmp::max(log_level(), tracing_level());
     unsafe {
         raw::rs_trace_set_level(level);
     }
 }
 
 #[cfg(feature = "log")]
+fn log_level() -> raw::rs_loglevel {
+    use log::LevelFilter;
+
+    match log::max_level() {
+        LevelFilter::Info => raw::RS_LOG_NOTICE,
+        LevelFilter::Debug | LevelFilter::Trace => raw::RS_LOG_DEBUG,
+        _ => raw::RS_LOG_WARNING,
+    }
+}
+
+#[cfg(all(feature = "tracing", not(feature = "log")))]
+fn log_level() -> raw::rs_loglevel {
+    raw::RS_LOG_EMERG
+}
+
+#[cfg(feature = "tracing")]
+fn tracing_level() -> raw::rs_loglevel {
+    use tracing::level_filters::LevelFilter;
+
+    match LevelFilter::current() {
+        LevelFilter::INFO => raw::RS_LOG_NOTICE,
+        LevelFilter::DEBUG | LevelFilter::TRACE => raw::RS_LOG_DEBUG,
+        _ => raw::RS_LOG_WARNING,
+    }
+}
+
+#[cfg(all(feature = "log", not(feature = "tracing")))]
+fn tracing_level() -> raw::rs_loglevel {
+    raw::RS_LOG_EMERG
+}
+
+#[cfg(any(feature = "log", feature = "tracing"))]
 extern "C" fn trace(level: raw::rs_loglevel, msg: *const c_char) {
-    use log::Level;
+    use std::panic::{self, AssertUnwindSafe};
+
+    // unwinding into librsync is undefined behavior, so a panicking logger is ignored
+    let _ = panic::catch_unwind(AssertUnwindSafe(|| forward(level, msg)));
+}
+
+#[cfg(any(feature = "log", feature = "tracing"))]
+fn forward(level: raw::rs_loglevel, msg: *const c_char) {
     use std::ffi::CStr;
 
+    let msg = unsafe { CStr::from_ptr(msg).to_string_lossy() };
+    #[cfg(feature = "log")]
+    forward_log(level, &msg);
+    #[cfg(feature = "tracing")]
+    forward_tracing(level, &msg);
+}
+
+#[cfg(feature = "log")]
+fn forward_log(level: raw::rs_loglevel, msg: &str) {
+    use log::Level;
+
     let level = match level {
         raw::RS_LOG_EMERG | raw::RS_LOG_ALERT | raw::RS_LOG_CRIT | raw::RS_LOG_ERR => Level::Error,
         raw::RS_LOG_WARNING => Level::Warn,
@@ -50,11 +96,21 @@ extern "C" fn trace(level: raw::rs_loglevel, msg: *const c_char) {
         raw::RS_LOG_DEBUG => Level::Debug,
         _ => Level::Error,
     };
-    let msg = unsafe { CStr::from_ptr(msg).to_string_lossy() };
     log!(target: "librsync", level, "{}", msg);
 }
 
-#[cfg(not(feature = "log"))]
+#[cfg(feature = "tracing")]
+fn forward_tracing(level: raw::rs_loglevel, msg: &str) {
+    // the level of tracing events must be a constant
+    match level {
+        raw::RS_LOG_WARNING => tracing::warn!(target: "librsync", "{}", msg),
+        raw::RS_LOG_NOTICE | raw::RS_LOG_INFO => tracing::info!(target: "librsync", "{}", msg),
+        raw::RS_LOG_DEBUG => tracing::debug!(target: "librsync", "{}", msg),
+        _ => tracing::error!(target: "librsync", "{}", msg),
+    }
+}
+
+#[cfg(not(any(feature = "log", feature = "tracing")))]
 fn init_impl() {
     unsafe {
         raw::rs_trace_to(trace);
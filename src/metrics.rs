@@ -0,0 +1,28 @@
+use crate::Stats;
+
+/// The operation performed by a librsync job.
+#[derive(Clone, Copy, Debug, Eq, PartialEq, Hash)]
+pub enum OpKind {
+    /// The computation of a signature.
+    Signature,
+    /// The computation of a delta.
+    Delta,
+    /// The application of a delta.
+    Patch,
+}
+
+/// A collector of events about the lifecycle of librsync jobs.
+///
+/// A collector is installed on a stream with `with_metrics`, for example to update Prometheus
+/// counters. All the methods do nothing by default, so that implementations only override the
+/// events they are interested in. When no collector is installed, no event is produced.
+pub trait Metrics: Send {
+    /// Called once, before the first iteration of the job.
+    fn on_start(&mut self, _op: OpKind) {}
+
+    /// Called every time librsync is given some input or output buffer to work on.
+    fn on_iteration(&mut self) {}
+
+    /// Called once, when the job has produced all its output, with its final statistics.
+    fn on_done(&mut self, _stats: &Stats) {}
+}
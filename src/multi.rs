@@ -0,0 +1,156 @@
+use crate::ReadAndSeek;
+
+use std::cmp;
+use std::io::{self, Read, Seek, SeekFrom};
+
+/// An adapter reading from several streams, one after the other.
+///
+/// This presents a file split in several segments, for example the parts of a multipart upload,
+/// as a single contiguous stream, which can be fed to `Signature` and `Delta`. Each stream is
+/// read until its end, before moving to the next one.
+pub struct MultiReader<'a> {
+    readers: Vec<Box<dyn Read + 'a>>,
+    current: usize,
+}
+
+/// An adapter reading and seeking over several segments, as a single stream.
+///
+/// This is the seekable counterpart of `MultiReader`, to be used as the basis file of a `Patch`.
+/// Every segment is given along with its length, so that the offsets in the whole stream can be
+/// mapped to the right segment and to the offset within it. A read never crosses the end of a
+/// segment, and a segment ending before its declared length is reported as an
+/// `ErrorKind::UnexpectedEof` error.
+pub struct MultiSeekReader<'a> {
+    segments: Vec<(Box<dyn ReadAndSeek + 'a>, u64)>,
+    // the offset of the first byte of every segment
+    starts: Vec<u64>,
+    len: u64,
+    pos: u64,
+}
+
+impl<'a> MultiReader<'a> {
+    /// Creates a new reader over the concatenation of the given streams.
+    pub fn new(readers: Vec<Box<dyn Read + 'a>>) -> Self {
+        MultiReader {
+            readers,
+            current: 0,
+        }
+    }
+
+    /// Unwraps this reader, returning the underlying streams.
+    pub fn into_inner(self) -> Vec<Box<dyn Read + 'a>> {
+        self.readers
+    }
+}
+
+impl<'a> Read for MultiReader<'a> {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        if buf.is_empty() {
+            return Ok(0);
+        }
+        while let Some(reader) = self.readers.get_mut(self.current) {
+            let read = reader.read(buf)?;
+            if read > 0 {
+                return Ok(read);
+            }
+            self.current += 1;
+        }
+        Ok(0)
+    }
+}
+
+impl<'a> MultiSeekReader<'a> {
+    /// Creates a new reader over the concatenation of the given segments.
+    ///
+    /// Every segment is paired with its length, in bytes. The reader starts at the beginning of
+    /// the first segment.
+    pub fn new(segments: Vec<(Box<dyn ReadAndSeek + 'a>, u64)>) -> Self {
+        let mut starts = Vec::with_capacity(segments.len());
+        let mut len = 0;
+        for &(_, seg_len) in &segments {
+            starts.push(len);
+            len += seg_len;
+        }
+        MultiSeekReader {
+            segments,
+            starts,
+            len,
+            pos: 0,
+        }
+    }
+
+    /// Returns the total length of the segments, in bytes.
+    pub fn len(&self) -> u64 {
+        self.len
+    }
+
+    /// Returns whether the total length of the segments is zero.
+    pub fn is_empty(&self) -> bool {
+        self.len == 0
+    }
+
+    /// Unwraps this reader, returning the underlying segments with their lengths.
+    pub fn into_inner(self) -> Vec<(Box<dyn ReadAndSeek + 'a>, u64)> {
+        self.segments
+    }
+}
+
+impl<'a> Read for MultiSeekReader<'a> {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        if buf.is_empty() || self.pos >= self.len {
+            return Ok(0);
+        }
+        // the last segment starting at or before the position, skipping the empty ones
+        let index = match self.starts.binary_search(&self.pos) {
+            Ok(mut index) => {
+                while self.segments[index].1 == 0 {
+                    index += 1;
+                }
+                index
+            }
+            Err(index) => index - 1,
+        };
+        let offset = self.pos - self.starts[index];
+        let seg_len = self.segments[index].1;
+        let segment = &mut self.segments[index].0;
+        let len = cmp::min(buf.len() as u64, seg_len - offset) as usize;
+        segment.seek(SeekFrom::Start(offset))?;
+        let read = segment.read(&mut buf[..len])?;
+        if read == 0 {
+            return Err(io::Error::new(
+                io::ErrorKind::UnexpectedEof,
+                "segment shorter than its length",
+            ));
+        }
+        self.pos += read as u64;
+        Ok(read)
+    }
+}
+
+impl<'a> Seek for MultiSeekReader<'a> {
+    fn seek(&mut self, pos: SeekFrom) -> io::Result<u64> {
+        let (base, offset) = match pos {
+            SeekFrom::Start(offset) => {
+                self.pos = offset;
+                return Ok(offset);
+            }
+            SeekFrom::Current(offset) => (self.pos, offset),
+            SeekFrom::End(offset) => (self.len, offset),
+        };
+        let pos = if offset >= 0 {
+            base.checked_add(offset as u64)
+        } else {
+            base.checked_sub(offset.unsigned_abs())
+        };
+        match pos {
+            Some(pos) => {
+                self.pos = pos;
+                Ok(pos)
+            }
+            None => Err(io::Error::new(
+                io::ErrorKind::InvalidInput,
+                "invalid seek to a negative or overflowing position",
+            )),
+        }
+    }
+}
@@ -8,7 +8,20 @@
 //! `Patch` structs.
 
 use super::*;
-use std::io::{self, BufRead, Read, Seek, Write};
+use crate::command;
+use crate::digest::HashWriter;
+use std::cell::RefCell;
+use std::collections::VecDeque;
+use std::ffi::OsString;
+use std::fmt::{self, Display, Formatter};
+use std::fs::{self, File, OpenOptions};
+use std::io::{self, BufRead, BufReader, BufWriter, Read, Seek, Write};
+use std::mem;
+use std::path::{Path, PathBuf};
+use std::process;
+use std::rc::Rc;
+use std::thread;
+use std::time::Duration;
 
 /// Generates the signature of a basis input, and writes it out to an output stream.
 ///
@@ -37,6 +50,25 @@ where
     Ok(written)
 }
 
+/// Generates the signature of a basis input, returning the parameters of the signature.
+///
+/// This function behaves like `signature_with_options`, but in case of success the parameters
+/// of the written signature are returned, instead of the number of bytes written.
+pub fn signature_with_options_info<R: ?Sized, W: ?Sized>(
+    input: &mut R,
+    output: &mut W,
+    block_len: usize,
+    strong_len: usize,
+    sig_type: SignatureType,
+) -> Result<SignatureInfo>
+where
+    R: BufRead,
+    W: Write,
+{
+    let mut sig = Signature::with_options(input, block_len, strong_len, sig_type)?;
+    copy_signature(&mut sig, output)
+}
+
 /// Generates the signature of a basis input, by using default settings.
 ///
 /// This function will consume the given input stream and attempt to write the resulting signature
@@ -53,6 +85,159 @@ where
     Ok(written)
 }
 
+/// Generates the signature of a basis input by using default settings, returning its parameters.
+///
+/// This function behaves like `signature`, but in case of success the parameters of the written
+/// signature are returned, instead of the number of bytes written. The returned `SignatureInfo`
+/// is read from the produced signature itself, so it reports the actual parameters chosen by the
+/// defaults.
+pub fn signature_info<R: ?Sized, W: ?Sized>(input: &mut R, output: &mut W) -> Result<SignatureInfo>
+where
+    R: Read,
+    W: Write,
+{
+    let mut sig = Signature::new(input)?;
+    copy_signature(&mut sig, output)
+}
+
+/// Generates the signature of a file, by mapping it in memory.
+///
+/// This function behaves like `signature_with_options`, but instead of reading the file through
+/// a buffer, it maps it read-only in memory, and feeds librsync directly from the mapped region.
+/// This avoids copying the data, and is faster for big local files. Empty files, which cannot be
+/// mapped, are read normally. In case of success, the statistics of the signature job are
+/// returned.
+///
+/// The file must not be modified while the signature is computed, or the result is undefined.
+#[cfg(feature = "mmap")]
+pub fn signature_mmap<W: ?Sized>(
+    path: &Path,
+    output: &mut W,
+    block_len: usize,
+    strong_len: usize,
+    sig_type: SignatureType,
+) -> Result<Stats>
+where
+    W: Write,
+{
+    let file = File::open(path)?;
+    if file.metadata()?.len() == 0 {
+        let mut sig = Signature::with_options(file, block_len, strong_len, sig_type)?;
+        sig.copy_to(output)?;
+        return Ok(sig.stats());
+    }
+    let map = unsafe { memmap2::Mmap::map(&file)? };
+    let mut sig = Signature::with_buf_read(&map[..], block_len, strong_len, sig_type)?;
+    sig.copy_to(output)?;
+    Ok(sig.stats())
+}
+
+/// Generates the signature of a basis input of known length, into a new `Vec`.
+///
+/// The length of a signature only depends on the length of the input and on the signature
+/// parameters, so the returned `Vec` is allocated once, with the exact capacity. The
+/// `input_len` parameter is the length of the input in bytes. If it is not accurate, the
+/// signature is still correct, but the `Vec` may need to be reallocated. See
+/// `signature_with_options` for details on the other arguments.
+pub fn signature_to_vec<R: ?Sized>(
+    input: &mut R,
+    input_len: u64,
+    block_len: usize,
+    strong_len: usize,
+    sig_type: SignatureType,
+) -> Result<Vec<u8>>
+where
+    R: Read,
+{
+    let sig_len = sig_type.signature_len(block_len, strong_len, input_len);
+    let mut output = Vec::with_capacity(sig_len as usize);
+    Signature::with_options(input, block_len, strong_len, sig_type)?.read_to_end(&mut output)?;
+    Ok(output)
+}
+
+/// Generates the signature of an input, while forwarding the input unchanged to another stream.
+///
+/// The input is read once: every chunk read is written to `passthrough`, and the signature of
+/// the whole input, with default settings, is written to `sig_out`. This allows, for example, an
+/// upload proxy to forward a file and keep its signature in a single pass. Both outputs may
+/// accept partial writes. The first error, either reading the input or writing to any of the
+/// outputs, stops the process and is returned. In case of success, the parameters of the
+/// written signature are returned.
+pub fn sign_and_forward<R: Read, W1: Write, W2: Write>(
+    input: R,
+    sig_out: &mut W1,
+    passthrough: &mut W2,
+) -> Result<SignatureInfo> {
+    let forwarder = Forwarder {
+        inner: input,
+        out: passthrough,
+    };
+    let mut sig = Signature::new(forwarder)?;
+    copy_signature(&mut sig, sig_out)
+}
+
+// Reads from a stream, writing the read data to another stream.
+struct Forwarder<'a, R, W: ?Sized + 'a> {
+    inner: R,
+    out: &'a mut W,
+}
+
+impl<'a, R: Read, W: Write + ?Sized> Read for Forwarder<'a, R, W> {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        let read = self.inner.read(buf)?;
+        self.out.write_all(&buf[..read])?;
+        Ok(read)
+    }
+}
+
+/// Compares the content of two signatures.
+///
+/// This function returns `true` only if both signatures have the same format, block length and
+/// strong checksums length, and the same checksums for all the blocks, in order. The signatures
+/// are compared while they are read, one block at a time, so they are not loaded in memory. An
+/// error is reported if any of the signatures is invalid, or truncated.
+pub fn signatures_equal<A: ?Sized, B: ?Sized>(a: &mut A, b: &mut B) -> Result<bool>
+where
+    A: Read,
+    B: Read,
+{
+    let mut header_a = [0; SignatureInfo::HEADER_LEN];
+    let mut header_b = [0; SignatureInfo::HEADER_LEN];
+    a.read_exact(&mut header_a)?;
+    b.read_exact(&mut header_b)?;
+    let info = SignatureInfo::parse(&header_a)?;
+    if info != SignatureInfo::parse(&header_b)? {
+        return Ok(false);
+    }
+    loop {
+        match (
+            read_block_sig(a, info.strong_len)?,
+            read_block_sig(b, info.strong_len)?,
+        ) {
+            (None, None) => return Ok(true),
+            (Some(entry_a), Some(entry_b)) if entry_a == entry_b => (),
+            _ => return Ok(false),
+        }
+    }
+}
+
+/// Counts the blocks described by a signature.
+///
+/// The signature body is scanned until its end, one entry at a time, so this works when the
+/// length of the basis file is unknown. If the length is known, `SignatureInfo::block_count`
+/// gives the same result without reading the signature. An error is reported if the signature
+/// is invalid, or if its last entry is truncated.
+pub fn count_blocks<R: Read>(mut sig: R) -> Result<u64> {
+    let mut header = [0; SignatureInfo::HEADER_LEN];
+    sig.read_exact(&mut header)?;
+    let info = SignatureInfo::parse(&header)?;
+    let mut blocks = 0;
+    while read_block_sig(&mut sig, info.strong_len)?.is_some() {
+        blocks += 1;
+    }
+    Ok(blocks)
+}
+
 /// Generates a delta between a signature and a new file streams.
 ///
 /// This function will consume the new file and base signature inputs and writes to the given
@@ -61,6 +246,11 @@ where
 /// modified file with respect to some base, for which its signature is provided as `base_sig`
 /// parameter.
 ///
+/// The delta is written with `write_all` semantics: partial writes are continued, and writes
+/// interrupted by `ErrorKind::Interrupted` are retried, while any other error, or a write
+/// accepting no bytes, is returned. The output is only written to, never seeked, so a `File`
+/// opened in append mode can be given to append the delta to an existing archive.
+///
 /// To generate a signature, see the `signature` function, or the `Signature` struct.
 pub fn delta<R: ?Sized, S: ?Sized, W: ?Sized>(
     new: &mut R,
@@ -77,6 +267,348 @@ where
     Ok(written)
 }
 
+/// Generates a delta between a signature and a new file streams, merging adjacent copies.
+///
+/// This function behaves like `delta`, but the copy commands referring to contiguous regions of
+/// the basis file are merged into a single command. This reduces the size of the delta and the
+/// number of commands to be executed by the patch, which produces the same result. See
+/// `coalesce_delta` for details.
+pub fn delta_coalesced<R: ?Sized, S: ?Sized, W: ?Sized>(
+    new: &mut R,
+    base_sig: &mut S,
+    output: &mut W,
+) -> Result<u64>
+where
+    R: Read,
+    S: Read,
+    W: Write,
+{
+    let delta = Delta::new(new, base_sig)?;
+    coalesce_delta(&mut BufReader::new(delta), output)
+}
+
+/// Rewrites a delta, merging the copy commands referring to contiguous regions of the basis file.
+///
+/// This function reads the delta from the `delta` stream, and writes to the given output an
+/// equivalent delta, where each sequence of copy commands reading adjacent regions of the basis
+/// file is replaced by a single copy command. The commands are also written with their shortest
+/// encoding. The result is a valid delta, producing the same output when patched. In case of
+/// success, the number of bytes written is returned, otherwise an error is reported.
+pub fn coalesce_delta<D: ?Sized, W: ?Sized>(delta: &mut D, output: &mut W) -> Result<u64>
+where
+    D: Read,
+    W: Write,
+{
+    command::coalesce(delta, output)
+}
+
+/// Generates a delta between a signature and a new file streams, avoiding short copies.
+///
+/// This function behaves like `delta`, but the copy commands shorter than `min_copy_len` bytes
+/// are replaced by literal commands with the same data, taken from the `new` input while it is
+/// read, and merged with the adjacent literals. A copy command costs a few bytes of header, and
+/// a read of the basis file when patching, so very short copies may not be worth it. The result
+/// is a valid delta, producing the same output when patched. In case of success, the number of
+/// bytes written is returned, otherwise an error is reported.
+pub fn delta_min_match<R: ?Sized, S: ?Sized, W: ?Sized>(
+    new: &mut R,
+    base_sig: &mut S,
+    output: &mut W,
+    min_copy_len: u64,
+) -> Result<u64>
+where
+    R: Read,
+    S: Read,
+    W: Write,
+{
+    let consumed = Rc::new(RefCell::new(VecDeque::new()));
+    let recorder = Recorder {
+        inner: new,
+        data: consumed.clone(),
+    };
+    let delta = Delta::new(recorder, base_sig)?;
+    let mut out = command::CountingWriter {
+        inner: output,
+        count: 0,
+    };
+    out.write_all(&command::DELTA_MAGIC)?;
+    let mut literal = Vec::new();
+    for cmd in DeltaCommands::new(BufReader::new(delta)) {
+        let cmd = cmd?;
+        let len = match cmd {
+            Command::Literal(ref data) => data.len() as u64,
+            Command::Copy { len, .. } => len,
+        };
+        let mut consumed = consumed.borrow_mut();
+        if len > consumed.len() as u64 {
+            return Err(Error::Internal);
+        }
+        let data = consumed.drain(..len as usize);
+        match cmd {
+            Command::Copy { .. } if len >= min_copy_len => {
+                Command::Literal(mem::take(&mut literal)).write_to(&mut out)?;
+                cmd.write_to(&mut out)?;
+            }
+            _ => literal.extend(data),
+        }
+    }
+    Command::Literal(literal).write_to(&mut out)?;
+    command::write_end(&mut out)?;
+    Ok(out.count)
+}
+
+// Reads from a stream, keeping the read data.
+struct Recorder<'a, R: ?Sized + 'a> {
+    inner: &'a mut R,
+    data: Rc<RefCell<VecDeque<u8>>>,
+}
+
+impl<'a, R: Read + ?Sized> Read for Recorder<'a, R> {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        let read = self.inner.read(buf)?;
+        self.data.borrow_mut().extend(&buf[..read]);
+        Ok(read)
+    }
+}
+
+/// Generates a delta between a signature and a new file streams, compressed with gzip.
+///
+/// This function behaves like `delta`, but the delta is compressed with gzip before being
+/// written to the output, at the given compression `level`, from 0 to 9. In case of success,
+/// the statistics of the delta job are returned, where `out_bytes` is the size of the
+/// uncompressed delta, together with the size of the compressed delta written to the output.
+///
+/// To apply a compressed delta, see `patch_gz`.
+#[cfg(feature = "gzip")]
+pub fn delta_gz<R: ?Sized, S: ?Sized, W: ?Sized>(
+    new: &mut R,
+    base_sig: &mut S,
+    output: &mut W,
+    level: u32,
+) -> Result<(Stats, u64)>
+where
+    R: Read,
+    S: Read,
+    W: Write,
+{
+    use flate2::write::GzEncoder;
+    use flate2::Compression;
+
+    let mut delta = Delta::new(new, base_sig)?;
+    let mut output = command::CountingWriter {
+        inner: output,
+        count: 0,
+    };
+    let mut encoder = GzEncoder::new(&mut output, Compression::new(level));
+    delta.copy_to(&mut encoder)?;
+    encoder.finish()?;
+    Ok((delta.stats(), output.count))
+}
+
+/// Checks whether a new file is identical to the basis file of a signature.
+///
+/// This function computes the delta between the signature of the basis file (`base_sig`
+/// parameter) and the new file (`new` parameter), and returns `true` if it contains no literal
+/// data, and copies the whole basis file in order. Backup tools can use it to skip storing the
+/// delta of unchanged files. The signature is loaded in memory, and the delta is discarded.
+///
+/// Like any delta, the result relies on the strong checksums of the signature, so weak
+/// signatures, with short or MD4 checksums, are more prone to collisions.
+pub fn is_unchanged<S: ?Sized, R: ?Sized>(base_sig: &mut S, new: &mut R) -> Result<bool>
+where
+    S: Read,
+    R: Read,
+{
+    let mut sig = Vec::new();
+    base_sig.read_to_end(&mut sig)?;
+    let info = SignatureInfo::parse(&sig)?;
+    let blocks = ((sig.len() - SignatureInfo::HEADER_LEN) / (4 + info.strong_len)) as u64;
+    let delta = Delta::new(new, &mut &sig[..])?;
+    let mut copied = 0;
+    for cmd in DeltaCommands::new(BufReader::new(delta)) {
+        match cmd? {
+            Command::Copy { offset, len } if offset == copied => copied += len,
+            _ => return Ok(false),
+        }
+    }
+    // the copied length must be the one of the basis file, whose last block may be shorter
+    let block_len = info.block_len as u64;
+    Ok(match blocks {
+        0 => copied == 0,
+        n => (n - 1) * block_len < copied && copied <= n * block_len,
+    })
+}
+
+/// A summary of the commands of a delta.
+///
+/// It is returned by `describe_delta`, and its `Display` implementation gives a human readable
+/// report, for support and debugging.
+#[derive(Clone, Debug, Default, Eq, PartialEq)]
+pub struct DeltaReport {
+    /// The number of copy commands.
+    pub copies: u64,
+    /// The number of literal commands.
+    pub literals: u64,
+    /// The number of bytes copied from the basis file.
+    pub copy_bytes: u64,
+    /// The number of bytes of literal data.
+    pub literal_bytes: u64,
+    /// The offset and the length of the longest copy, if any.
+    pub largest_copy: Option<(u64, u64)>,
+    /// The regions of the basis file copied at least once, as offsets and lengths, sorted and
+    /// merged when they overlap or touch.
+    pub coverage: Vec<(u64, u64)>,
+}
+
+impl Display for DeltaReport {
+    fn fmt(&self, fmt: &mut Formatter) -> fmt::Result {
+        write!(
+            fmt,
+            "{} copies, {} literals, {} literal bytes",
+            self.copies, self.literals, self.literal_bytes
+        )?;
+        if let Some((offset, len)) = self.largest_copy {
+            write!(fmt, ", largest copy {}@{}", len, offset)?;
+        }
+        writeln!(fmt)?;
+        writeln!(
+            fmt,
+            "output: {} bytes, {} copied, {} literal",
+            self.copy_bytes + self.literal_bytes,
+            self.copy_bytes,
+            self.literal_bytes
+        )?;
+        write!(fmt, "base coverage:")?;
+        if self.coverage.is_empty() {
+            write!(fmt, " none")?;
+        }
+        for &(offset, len) in &self.coverage {
+            write!(fmt, " {}..{}", offset, offset + len)?;
+        }
+        writeln!(fmt)
+    }
+}
+
+/// Summarizes the commands of a delta.
+///
+/// The delta is parsed with `DeltaCommands`, and its commands are counted into a
+/// `DeltaReport`, along with the regions of the basis file they copy. Every literal command is
+/// loaded in memory while it is counted.
+pub fn describe_delta<R: Read>(delta: R) -> Result<DeltaReport> {
+    let mut report = DeltaReport::default();
+    let mut regions = Vec::new();
+    for cmd in DeltaCommands::new(BufReader::new(delta)) {
+        match cmd? {
+            Command::Literal(data) => {
+                report.literals += 1;
+                report.literal_bytes += data.len() as u64;
+            }
+            Command::Copy { offset, len } => {
+                report.copies += 1;
+                report.copy_bytes += len;
+                if !matches!(report.largest_copy, Some((_, max)) if max >= len) {
+                    report.largest_copy = Some((offset, len));
+                }
+                regions.push((offset, len));
+            }
+        }
+    }
+    regions.sort_unstable();
+    for (offset, len) in regions {
+        match report.coverage.last_mut() {
+            Some(&mut (start, ref mut merged)) if offset <= start + *merged => {
+                *merged = cmp::max(*merged, offset + len - start);
+            }
+            _ => report.coverage.push((offset, len)),
+        }
+    }
+    Ok(report)
+}
+
+/// Generates a delta between a basis and a new file, without storing the basis signature.
+///
+/// This function computes the signature of the `base` input in memory, and then uses it to
+/// write to the given output the delta between `base` and `new`. Both inputs are streamed and
+/// read once, while the signature of the basis is kept in memory. In case of success, the
+/// statistics of the delta job are returned, otherwise an error is reported.
+///
+/// See `signature_with_options` for details on the `block_len`, `strong_len` and `sig_type`
+/// arguments.
+pub fn delta_from_files<B: ?Sized, R: ?Sized, W: ?Sized>(
+    base: &mut B,
+    new: &mut R,
+    output: &mut W,
+    block_len: usize,
+    strong_len: usize,
+    sig_type: SignatureType,
+) -> Result<Stats>
+where
+    B: Read,
+    R: Read,
+    W: Write,
+{
+    let mut sig = Vec::new();
+    Signature::with_options(base, block_len, strong_len, sig_type)?.read_to_end(&mut sig)?;
+    let mut delta = Delta::new(new, &mut &sig[..])?;
+    io::copy(&mut delta, output)?;
+    Ok(delta.stats())
+}
+
+/// Generates the deltas between two revisions of a file, in both directions.
+///
+/// The forward delta patches `old` into `new`, while the reverse delta patches `new` back into
+/// `old`. Each one is computed by `delta_from_files`, with the roles of the two revisions
+/// swapped, and returned in this order. This allows to move back and forth between the
+/// revisions, by keeping only one of them along with the deltas.
+///
+/// See `signature_with_options` for details on the `block_len`, `strong_len` and `sig_type`
+/// arguments.
+pub fn bidirectional_delta(
+    old: &[u8],
+    new: &[u8],
+    block_len: usize,
+    strong_len: usize,
+    sig_type: SignatureType,
+) -> Result<(Vec<u8>, Vec<u8>)> {
+    let mut forward = Vec::new();
+    delta_from_files(
+        &mut io::Cursor::new(old),
+        &mut io::Cursor::new(new),
+        &mut forward,
+        block_len,
+        strong_len,
+        sig_type,
+    )?;
+    let mut reverse = Vec::new();
+    delta_from_files(
+        &mut io::Cursor::new(new),
+        &mut io::Cursor::new(old),
+        &mut reverse,
+        block_len,
+        strong_len,
+        sig_type,
+    )?;
+    Ok((forward, reverse))
+}
+
+/// Generates a delta between a signature and a new file streams, into a new `Vec`.
+///
+/// This function behaves like `delta`, but returns the delta in a `Vec` allocated with
+/// `size_hint` bytes of capacity, to avoid reallocations when the delta size can be estimated.
+pub fn delta_to_vec<R: ?Sized, S: ?Sized>(
+    new: &mut R,
+    base_sig: &mut S,
+    size_hint: usize,
+) -> Result<Vec<u8>>
+where
+    R: Read,
+    S: Read,
+{
+    let mut output = Vec::with_capacity(size_hint);
+    Delta::new(new, base_sig)?.read_to_end(&mut output)?;
+    Ok(output)
+}
+
 /// Applies a patch, relative to a basis, into an output stream.
 ///
 /// This function will consume the base file and the new file delta inputs and writes to the given
@@ -102,11 +634,394 @@ where
     Ok(written)
 }
 
+/// Applies a patch compressed with gzip, relative to a basis, into an output stream.
+///
+/// This function behaves like `patch`, but decompresses the delta, produced by `delta_gz`, while
+/// applying it. In case of success, the number of bytes written is returned.
+#[cfg(feature = "gzip")]
+pub fn patch_gz<B: ?Sized, D: ?Sized, W: ?Sized>(
+    base: &mut B,
+    delta: &mut D,
+    output: &mut W,
+) -> Result<u64>
+where
+    B: Read + Seek,
+    D: Read,
+    W: Write,
+{
+    let mut delta = flate2::read::GzDecoder::new(delta);
+    patch(base, &mut delta, output)
+}
+
+/// The length of the zero regions turned into holes by `patch_sparse`, in bytes.
+const SPARSE_BLOCK_LEN: usize = 4096;
+
+/// Applies a patch, relative to a basis, into a sparse file.
+///
+/// This function behaves like `patch`, but the output is written to `out` starting at its
+/// current position, skipping the regions of zeros instead of writing them, so that they become
+/// holes on file systems supporting sparse files. The output is split in blocks of 4 KiB, and
+/// the blocks containing only zeros are skipped. The file is first truncated at its current
+/// position, so the skipped regions read back as zeros, and the file is finally extended to the
+/// full length of the output. In case of success, the statistics of the patch job are returned.
+pub fn patch_sparse<B: ?Sized, D: ?Sized>(base: &mut B, delta: &mut D, out: &File) -> Result<Stats>
+where
+    B: Read + Seek,
+    D: Read,
+{
+    let mut out = out;
+    let start = out.stream_position()?;
+    out.set_len(start)?;
+    let mut patch = Patch::new(base, delta)?;
+    let mut block = vec![0; SPARSE_BLOCK_LEN];
+    let mut len = 0;
+    // the length of the zeros skipped since the last write
+    let mut hole = 0;
+    loop {
+        let read = read_full(&mut patch, &mut block)?;
+        if read == 0 {
+            break;
+        }
+        len += read as u64;
+        if block[..read].iter().all(|&b| b == 0) {
+            hole += read as i64;
+            continue;
+        }
+        if hole > 0 {
+            out.seek(io::SeekFrom::Current(hole))?;
+            hole = 0;
+        }
+        out.write_all(&block[..read])?;
+    }
+    out.set_len(start + len)?;
+    Ok(patch.stats())
+}
+
+// Fills the buffer from the input, unless it ends first, returning the number of bytes read.
+fn read_full<R: Read + ?Sized>(input: &mut R, buf: &mut [u8]) -> io::Result<usize> {
+    let mut read = 0;
+    while read < buf.len() {
+        match input.read(&mut buf[read..]) {
+            Ok(0) => break,
+            Ok(n) => read += n,
+            Err(ref e) if e.kind() == io::ErrorKind::Interrupted => (),
+            Err(e) => return Err(e),
+        }
+    }
+    Ok(read)
+}
+
+/// Applies a patch to a file, replacing it with the patched version.
+///
+/// The delta is applied to the given `file`, and the result is written to a temporary file in the
+/// same directory, which then atomically replaces the original one, keeping its permissions. If
+/// the patch fails, the original file is left untouched and the temporary file is removed. In
+/// case of success, the statistics of the patch job are returned.
+///
+/// Note that the original file cannot be overwritten while the patch is applied, because copy
+/// commands may refer to regions of the basis file which have already been overwritten. Enough
+/// disk space for both versions of the file is therefore needed.
+pub fn patch_in_place<D: ?Sized>(file: &Path, delta: &mut D) -> Result<Stats>
+where
+    D: Read,
+{
+    patch_in_place_with_retry(file, delta, RetryPolicy::default())
+}
+
+/// Applies a patch to a file, retrying the IO operations failing transiently.
+///
+/// This function behaves like `patch_in_place`, but every IO operation on the files and on the
+/// delta failing with an `ErrorKind::Interrupted`, `ErrorKind::WouldBlock` or
+/// `ErrorKind::TimedOut` error is retried according to the given policy, as it may happen on
+/// networked filesystems. The last error is returned once the attempts are exhausted.
+pub fn patch_in_place_with_retry<D: ?Sized>(
+    file: &Path,
+    delta: &mut D,
+    retry: RetryPolicy,
+) -> Result<Stats>
+where
+    D: Read,
+{
+    let base = retry.run(|| File::open(file))?;
+    let permissions = retry.run(|| base.metadata())?.permissions();
+    let (temp_path, temp) = retry.run(|| create_temp_file(file))?;
+    let res = (|| {
+        let mut output = BufWriter::new(Retrying::new(temp, retry));
+        let mut patch = Patch::new(Retrying::new(base, retry), Retrying::new(delta, retry))?;
+        io::copy(&mut patch, &mut output)?;
+        let temp = output.into_inner().map_err(|e| e.into_error())?.inner;
+        retry.run(|| temp.set_permissions(permissions.clone()))?;
+        retry.run(|| temp.sync_all())?;
+        retry.run(|| fs::rename(&temp_path, file))?;
+        Ok(patch.stats())
+    })();
+    if res.is_err() {
+        let _ = fs::remove_file(&temp_path);
+    }
+    res
+}
+
+/// A policy to retry the IO operations failing transiently.
+///
+/// An operation failing with an `ErrorKind::Interrupted`, `ErrorKind::WouldBlock` or
+/// `ErrorKind::TimedOut` error is attempted again after `delay`, for at most `max_attempts`
+/// attempts in total. The default policy makes a single attempt, so it never retries.
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub struct RetryPolicy {
+    /// The maximum number of attempts of an operation, including the first one.
+    pub max_attempts: u32,
+    /// The time to wait before retrying an operation.
+    pub delay: Duration,
+}
+
+impl RetryPolicy {
+    /// Creates a policy making at most `max_attempts` attempts, waiting `delay` between them.
+    pub fn new(max_attempts: u32, delay: Duration) -> Self {
+        RetryPolicy {
+            max_attempts,
+            delay,
+        }
+    }
+
+    // Runs the operation until it succeeds, fails permanently, or the attempts are exhausted.
+    fn run<T, F: FnMut() -> io::Result<T>>(&self, mut op: F) -> io::Result<T> {
+        let mut attempts = 1;
+        loop {
+            match op() {
+                Err(ref e) if attempts < self.max_attempts && is_retryable(e) => {
+                    attempts += 1;
+                    thread::sleep(self.delay);
+                }
+                res => return res,
+            }
+        }
+    }
+}
+
+impl Default for RetryPolicy {
+    fn default() -> Self {
+        RetryPolicy::new(1, Duration::from_secs(0))
+    }
+}
+
+fn is_retryable(e: &io::Error) -> bool {
+    is_transient(e) || e.kind() == io::ErrorKind::TimedOut
+}
+
+// Wraps a stream, retrying its operations according to a policy.
+struct Retrying<T> {
+    inner: T,
+    retry: RetryPolicy,
+}
+
+impl<T> Retrying<T> {
+    fn new(inner: T, retry: RetryPolicy) -> Self {
+        Retrying { inner, retry }
+    }
+}
+
+impl<T: Read> Read for Retrying<T> {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        let inner = &mut self.inner;
+        self.retry.run(|| inner.read(buf))
+    }
+}
+
+impl<T: Seek> Seek for Retrying<T> {
+    fn seek(&mut self, pos: io::SeekFrom) -> io::Result<u64> {
+        let inner = &mut self.inner;
+        self.retry.run(|| inner.seek(pos))
+    }
+}
+
+impl<T: Write> Write for Retrying<T> {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        let inner = &mut self.inner;
+        self.retry.run(|| inner.write(buf))
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        let inner = &mut self.inner;
+        self.retry.run(|| inner.flush())
+    }
+}
+
+// Creates a new temporary file, in the same directory of the given file.
+fn create_temp_file(file: &Path) -> io::Result<(PathBuf, File)> {
+    let name = file
+        .file_name()
+        .ok_or_else(|| io::Error::new(io::ErrorKind::InvalidInput, "not a file path"))?;
+    let mut attempt = 0;
+    loop {
+        let mut temp_name = OsString::from(".");
+        temp_name.push(name);
+        temp_name.push(format!(".patch-{}-{}", process::id(), attempt));
+        let temp_path = file.with_file_name(temp_name);
+        match OpenOptions::new()
+            .write(true)
+            .create_new(true)
+            .open(&temp_path)
+        {
+            Ok(temp) => return Ok((temp_path, temp)),
+            Err(ref e) if e.kind() == io::ErrorKind::AlreadyExists && attempt < 100 => attempt += 1,
+            Err(e) => return Err(e),
+        }
+    }
+}
+
+/// Computes the digest of a whole input stream.
+///
+/// This function will consume the given input stream and hash it with the given algorithm. The
+/// hash functions are the same used by librsync to compute the strong signatures, so no other
+/// hashing library is needed to match them. The result can be stored along with the file, and
+/// used to verify it later, for example with `patch_verified`.
+pub fn file_hash<R: ?Sized>(input: &mut R, kind: DigestKind) -> Result<Digest>
+where
+    R: Read,
+{
+    let mut sink = io::sink();
+    let mut output = HashWriter::new(&mut sink, kind)?;
+    io::copy(input, &mut output)?;
+    Ok(output.finish())
+}
+
+/// Applies a patch, relative to a basis, into an output stream, and verifies the result.
+///
+/// This function behaves like `patch`, but also computes the digest of the patched output, while
+/// writing it. If the digest doesn't match the `expected` one, `Error::Corrupt` is returned.
+/// The hash algorithm is the same used for the `expected` digest. In case of success, the
+/// statistics of the patch job are returned.
+///
+/// Note that the output is written out before the verification is completed, so it must be
+/// discarded by the caller in case of errors.
+pub fn patch_verified<B: ?Sized, D: ?Sized, W: ?Sized>(
+    base: &mut B,
+    delta: &mut D,
+    output: &mut W,
+    expected: &Digest,
+) -> Result<Stats>
+where
+    B: Read + Seek,
+    D: Read,
+    W: Write,
+{
+    let mut patch = Patch::new(base, delta)?;
+    let mut output = HashWriter::new(output, expected.kind())?;
+    io::copy(&mut patch, &mut output)?;
+    if output.finish() != *expected {
+        return Err(Error::Corrupt);
+    }
+    Ok(patch.stats())
+}
+
+/// Applies a patch, after checking the version of the delta format.
+///
+/// This function behaves like `patch`, but it first reads the magic number of the delta, and
+/// returns `Error::BadMagic` if it is not a delta, or if its format version is not the one
+/// supported by this crate, as reported by `Delta::format_version`. This gives a clear error on
+/// deltas produced by incompatible implementations, instead of failing in the middle of the
+/// patch. In case of success, the number of bytes written is returned.
+pub fn patch_checked<B: ?Sized, D: ?Sized, W: ?Sized>(
+    base: &mut B,
+    delta: &mut D,
+    output: &mut W,
+) -> Result<u64>
+where
+    B: Read + Seek,
+    D: Read,
+    W: Write,
+{
+    let mut magic = [0; 4];
+    delta.read_exact(&mut magic)?;
+    if command::format_version(magic)? != command::DELTA_MAGIC[2] {
+        return Err(Error::BadMagic);
+    }
+    patch(base, &mut (&magic[..]).chain(delta), output)
+}
+
+/// Applies a patch, relative to a basis, into a new `Vec`.
+///
+/// This function behaves like `patch`, but returns the patched file in a `Vec` allocated with
+/// `size_hint` bytes of capacity, to avoid reallocations when the new file size is known.
+pub fn patch_to_vec<B: ?Sized, D: ?Sized>(
+    base: &mut B,
+    delta: &mut D,
+    size_hint: usize,
+) -> Result<Vec<u8>>
+where
+    B: Read + Seek,
+    D: Read,
+{
+    let mut output = Vec::with_capacity(size_hint);
+    Patch::new(base, delta)?.read_to_end(&mut output)?;
+    Ok(output)
+}
+
+/// Runs a full signature, delta and patch cycle in memory, and returns the reconstructed file.
+///
+/// The signature of `base` is computed with the given parameters, then the delta between it and
+/// `new`, which is finally applied to `base`. The result should always be equal to `new`, so
+/// this is useful as a self-check of the pipeline, in tests or fuzz harnesses. The streaming
+/// types are chained together, so neither the signature nor the delta is stored entirely.
+///
+/// A zero `block_len` selects the default block length, like for the other signature functions,
+/// while a `strong_len` longer than the strong checksums of `sig_type` is reported as an
+/// `Error::InvalidParameter` error. See `signature_with_options` for details on the parameters.
+pub fn roundtrip(
+    base: &[u8],
+    new: &[u8],
+    block_len: usize,
+    strong_len: usize,
+    sig_type: SignatureType,
+) -> Result<Vec<u8>> {
+    let mut sig = Signature::with_options(base, block_len, strong_len, sig_type)?;
+    let delta = Delta::new(new, &mut sig)?;
+    let mut output = Vec::with_capacity(new.len());
+    Patch::new(io::Cursor::new(base), delta)?.read_to_end(&mut output)?;
+    Ok(output)
+}
+
+/// The maximum length of the output of `apply_untrusted`, in bytes.
+pub const UNTRUSTED_MAX_OUTPUT: u64 = 1 << 30;
+
+/// Applies a delta coming from an untrusted source to an in-memory basis file.
+///
+/// This is the recommended entry point for deltas which may be malformed or malicious, and the
+/// one to target with fuzzers. All the defensive checks are enabled: the output is limited to
+/// `UNTRUSTED_MAX_OUTPUT` bytes, copy commands with negative offsets or beyond the end of
+/// `base` are rejected, and panics while reading the basis file are caught. Any malformed
+/// delta, like one with a bad magic number, or truncated, is reported as an error. In case of
+/// success, the patched file is returned.
+pub fn apply_untrusted(base: &[u8], delta: &[u8]) -> Result<Vec<u8>> {
+    apply_limited(base, delta, UNTRUSTED_MAX_OUTPUT)
+}
+
+fn apply_limited(base: &[u8], delta: &[u8], max_output: u64) -> Result<Vec<u8>> {
+    let mut patch = Patch::with_max_output(io::Cursor::new(base), delta, max_output)?;
+    let mut output = Vec::new();
+    patch.read_to_end(&mut output)?;
+    Ok(output)
+}
+
+fn copy_signature<R: ?Sized, W: ?Sized>(sig: &mut R, output: &mut W) -> Result<SignatureInfo>
+where
+    R: Read,
+    W: Write,
+{
+    let mut header = [0; SignatureInfo::HEADER_LEN];
+    sig.read_exact(&mut header)?;
+    let info = SignatureInfo::parse(&header)?;
+    output.write_all(&header)?;
+    io::copy(sig, output)?;
+    Ok(info)
+}
+
 #[cfg(test)]
 mod test {
     use super::*;
     use crate::SignatureType;
 
+    use std::cmp;
     use std::io::Cursor;
     use std::str::from_utf8;
 
@@ -138,4 +1053,689 @@ mod test {
         let out_str = from_utf8(&out).unwrap();
         assert_eq!(out_str, DATA2);
     }
+
+    #[test]
+    fn signature_infos() {
+        let mut sig = Vec::new();
+        let info = signature_info(&mut Cursor::new(DATA), &mut sig).unwrap();
+        assert_eq!(info, SignatureInfo::parse(&sig).unwrap());
+        assert_eq!(info.sig_type, SignatureType::Blake2);
+        assert_eq!(info.strong_len, 32);
+        let mut plain = Vec::new();
+        let written = signature(&mut Cursor::new(DATA), &mut plain).unwrap();
+        assert_eq!(written, sig.len() as u64);
+        assert_eq!(plain, sig);
+
+        let mut sig = Vec::new();
+        let info = signature_with_options_info(
+            &mut Cursor::new(DATA),
+            &mut sig,
+            10,
+            5,
+            SignatureType::MD4,
+        )
+        .unwrap();
+        assert_eq!(info, SignatureInfo::parse(&sig).unwrap());
+        assert_eq!(info.block_len, 10);
+        let written = signature_with_options(
+            &mut Cursor::new(DATA),
+            &mut Vec::new(),
+            10,
+            5,
+            SignatureType::MD4,
+        )
+        .unwrap();
+        assert_eq!(written, sig.len() as u64);
+    }
+
+    #[test]
+    fn coalesced() {
+        // COPY(0, 5), COPY(5, 3), LITERAL("another "), COPY(10, 9), COPY(19, 10)
+        let mut split = vec![0x72, 0x73, 0x02, 0x36];
+        split.extend_from_slice(&[0x45, 0x00, 0x05, 0x45, 0x05, 0x03]);
+        split.extend_from_slice(b"\x08another ");
+        split.extend_from_slice(&[0x45, 0x0a, 0x09, 0x45, 0x13, 0x0a, 0x00]);
+        let mut expected = Vec::new();
+        patch(&mut Cursor::new(DATA), &mut &split[..], &mut expected).unwrap();
+        assert_eq!(from_utf8(&expected).unwrap(), DATA2);
+
+        let mut coalesced = Vec::new();
+        let written = coalesce_delta(&mut &split[..], &mut coalesced).unwrap();
+        assert_eq!(written, coalesced.len() as u64);
+        assert!(coalesced.len() < split.len());
+        let mut out = Vec::new();
+        patch(&mut Cursor::new(DATA), &mut &coalesced[..], &mut out).unwrap();
+        assert_eq!(out, expected);
+
+        let mut sig = Vec::new();
+        signature_with_options(&mut DATA.as_bytes(), &mut sig, 10, 5, SignatureType::MD4).unwrap();
+        let mut dlt = Vec::new();
+        delta_coalesced(&mut DATA2.as_bytes(), &mut &sig[..], &mut dlt).unwrap();
+        let mut out = Vec::new();
+        patch(&mut Cursor::new(DATA), &mut &dlt[..], &mut out).unwrap();
+        assert_eq!(from_utf8(&out).unwrap(), DATA2);
+
+        let bad = [0x72, 0x73, 0x02, 0x36, 0x60, 0x00];
+        match coalesce_delta(&mut &bad[..], &mut Vec::new()) {
+            Err(Error::Corrupt) => (),
+            _ => panic!("expected a corrupt delta error"),
+        }
+    }
+
+    #[test]
+    fn in_place() {
+        let dir = std::env::temp_dir().join(format!("librsync-in-place-{}", process::id()));
+        fs::create_dir_all(&dir).unwrap();
+        let file = dir.join("data");
+        fs::write(&file, DATA).unwrap();
+        #[cfg(unix)]
+        {
+            use std::os::unix::fs::PermissionsExt;
+            fs::set_permissions(&file, fs::Permissions::from_mode(0o640)).unwrap();
+        }
+
+        let mut sig = Vec::new();
+        signature_with_options(&mut DATA.as_bytes(), &mut sig, 10, 5, SignatureType::MD4).unwrap();
+        let mut dlt = Vec::new();
+        delta(&mut DATA2.as_bytes(), &mut &sig[..], &mut dlt).unwrap();
+
+        let stats = patch_in_place(&file, &mut &dlt[..]).unwrap();
+        assert_eq!(stats.out_bytes, DATA2.len() as u64);
+        assert_eq!(fs::read(&file).unwrap(), DATA2.as_bytes());
+        #[cfg(unix)]
+        {
+            use std::os::unix::fs::PermissionsExt;
+            let mode = fs::metadata(&file).unwrap().permissions().mode();
+            assert_eq!(mode & 0o777, 0o640);
+        }
+
+        // a failed patch leaves the file untouched
+        assert!(patch_in_place(&file, &mut &b"garbage"[..]).is_err());
+        assert_eq!(fs::read(&file).unwrap(), DATA2.as_bytes());
+        assert_eq!(fs::read_dir(&dir).unwrap().count(), 1);
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn delta_report() {
+        let dlt = [
+            0x72, 0x73, 0x02, 0x36, 0x10, 0x74, 0x68, 0x69, 0x73, 0x20, 0x69, 0x73, 0x20, 0x61,
+            0x6e, 0x6f, 0x74, 0x68, 0x65, 0x72, 0x20, 0x45, 0x0a, 0x13, 0x00,
+        ];
+        let report = describe_delta(&dlt[..]).unwrap();
+        assert_eq!(report.copies, 1);
+        assert_eq!(report.literals, 1);
+        assert_eq!(report.copy_bytes, 19);
+        assert_eq!(report.literal_bytes, 16);
+        assert_eq!(report.largest_copy, Some((10, 19)));
+        assert_eq!(report.coverage, vec![(10, 19)]);
+        let text = report.to_string();
+        assert!(text.lines().count() > 1);
+        assert!(text.starts_with("1 copies, 1 literals, 16 literal bytes, largest copy 19@10\n"));
+
+        // overlapping and touching copies are merged in the coverage
+        let dlt = [
+            0x72, 0x73, 0x02, 0x36, 0x45, 0x00, 0x05, 0x45, 0x05, 0x05, 0x45, 0x14, 0x04, 0x45,
+            0x02, 0x02, 0x00,
+        ];
+        let report = describe_delta(&dlt[..]).unwrap();
+        assert_eq!(report.copies, 4);
+        assert_eq!(report.largest_copy, Some((0, 5)));
+        assert_eq!(report.coverage, vec![(0, 10), (20, 4)]);
+
+        assert!(describe_delta(&b"garbage"[..]).is_err());
+    }
+
+    #[test]
+    fn checked_version() {
+        let mut sig = Vec::new();
+        signature_with_options(&mut DATA.as_bytes(), &mut sig, 10, 5, SignatureType::MD4).unwrap();
+        let mut dlt = Vec::new();
+        delta(&mut DATA2.as_bytes(), &mut &sig[..], &mut dlt).unwrap();
+
+        let mut out = Vec::new();
+        patch_checked(&mut Cursor::new(DATA), &mut &dlt[..], &mut out).unwrap();
+        assert_eq!(out, DATA2.as_bytes());
+
+        for &(index, byte) in &[(2, 0x03), (0, 0x00), (3, 0x37)] {
+            let mut bad = dlt.clone();
+            bad[index] = byte;
+            match patch_checked(&mut Cursor::new(DATA), &mut &bad[..], &mut Vec::new()) {
+                Err(Error::BadMagic) => (),
+                _ => panic!("expected a bad magic error"),
+            }
+        }
+    }
+
+    // A reader failing the first read with a timeout.
+    struct TimingOutReader<R> {
+        inner: R,
+        failed: bool,
+    }
+
+    impl<R: Read> Read for TimingOutReader<R> {
+        fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+            if !self.failed {
+                self.failed = true;
+                return Err(io::ErrorKind::TimedOut.into());
+            }
+            self.inner.read(buf)
+        }
+    }
+
+    #[test]
+    fn in_place_retry() {
+        let dir = std::env::temp_dir().join(format!("librsync-retry-{}", process::id()));
+        fs::create_dir_all(&dir).unwrap();
+        let file = dir.join("data");
+        fs::write(&file, DATA).unwrap();
+        let mut sig = Vec::new();
+        signature_with_options(&mut DATA.as_bytes(), &mut sig, 10, 5, SignatureType::MD4).unwrap();
+        let mut dlt = Vec::new();
+        delta(&mut DATA2.as_bytes(), &mut &sig[..], &mut dlt).unwrap();
+
+        let mut failing = TimingOutReader {
+            inner: &dlt[..],
+            failed: false,
+        };
+        assert!(patch_in_place(&file, &mut failing).is_err());
+        assert_eq!(fs::read(&file).unwrap(), DATA.as_bytes());
+
+        let mut failing = TimingOutReader {
+            inner: &dlt[..],
+            failed: false,
+        };
+        let retry = RetryPolicy::new(2, Duration::from_millis(1));
+        patch_in_place_with_retry(&file, &mut failing, retry).unwrap();
+        assert_eq!(fs::read(&file).unwrap(), DATA2.as_bytes());
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn setup_errors() {
+        // strong checksums longer than the MD4 digest
+        let res = signature_with_options(
+            &mut DATA.as_bytes(),
+            &mut Vec::new(),
+            10,
+            100,
+            SignatureType::MD4,
+        );
+        assert!(res.is_err());
+
+        // a delta is not a signature
+        let mut sig = Vec::new();
+        signature(&mut DATA.as_bytes(), &mut sig).unwrap();
+        let mut dlt = Vec::new();
+        delta(&mut DATA2.as_bytes(), &mut &sig[..], &mut dlt).unwrap();
+        assert!(delta(&mut DATA2.as_bytes(), &mut &dlt[..], &mut Vec::new()).is_err());
+        assert!(delta_from_files(
+            &mut DATA.as_bytes(),
+            &mut DATA2.as_bytes(),
+            &mut Vec::new(),
+            10,
+            100,
+            SignatureType::Blake2,
+        )
+        .is_err());
+
+        // a signature is not a delta
+        assert!(patch(&mut Cursor::new(DATA), &mut &sig[..], &mut Vec::new()).is_err());
+    }
+
+    #[test]
+    fn compare_signatures() {
+        let sign = |data: &[u8], block_len| {
+            let mut sig = Vec::new();
+            let mut input = data;
+            signature_with_options(&mut input, &mut sig, block_len, 8, SignatureType::Blake2)
+                .unwrap();
+            sig
+        };
+        let sig = sign(DATA.as_bytes(), 10);
+        assert!(signatures_equal(&mut &sig[..], &mut &sign(DATA.as_bytes(), 10)[..]).unwrap());
+
+        // a single different block
+        let mut changed = DATA.as_bytes().to_vec();
+        changed[12] = b'S';
+        assert!(!signatures_equal(&mut &sig[..], &mut &sign(&changed, 10)[..]).unwrap());
+        // different lengths
+        let longer = sign(DATA2.as_bytes(), 10);
+        assert!(!signatures_equal(&mut &sig[..], &mut &longer[..]).unwrap());
+        assert!(!signatures_equal(&mut &sig[..], &mut &sig[..sig.len() - 12]).unwrap());
+        // different parameters
+        assert!(!signatures_equal(&mut &sig[..], &mut &sign(DATA.as_bytes(), 16)[..]).unwrap());
+        // invalid signatures
+        assert!(signatures_equal(&mut &sig[..], &mut &b"garbage garbage"[..]).is_err());
+        assert!(signatures_equal(&mut &sig[..], &mut &sig[..sig.len() - 3]).is_err());
+    }
+
+    #[cfg(feature = "mmap")]
+    #[test]
+    fn mmap() {
+        let dir = std::env::temp_dir().join(format!("librsync-mmap-{}", process::id()));
+        fs::create_dir_all(&dir).unwrap();
+        let file = dir.join("data");
+        let data: Vec<u8> = (0..100_000u32).map(|i| (i % 251) as u8).collect();
+        for content in &[&data[..], &[]] {
+            fs::write(&file, content).unwrap();
+            let mut expected = Vec::new();
+            let mut input = *content;
+            signature_with_options(&mut input, &mut expected, 512, 16, SignatureType::Blake2)
+                .unwrap();
+            let mut sig = Vec::new();
+            let stats = signature_mmap(&file, &mut sig, 512, 16, SignatureType::Blake2).unwrap();
+            assert_eq!(sig, expected);
+            assert_eq!(stats.in_bytes, content.len() as u64);
+        }
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[cfg(feature = "gzip")]
+    #[test]
+    fn gzip() {
+        let base: Vec<u8> = b"some repetitive text "
+            .iter()
+            .cycle()
+            .take(10_000)
+            .cloned()
+            .collect();
+        let mut new = base.clone();
+        new.extend(b"appended repetitive text ".iter().cycle().take(10_000));
+        let mut sig = Vec::new();
+        signature(&mut &base[..], &mut sig).unwrap();
+
+        let mut dlt = Vec::new();
+        let (stats, compressed_len) = delta_gz(&mut &new[..], &mut &sig[..], &mut dlt, 6).unwrap();
+        assert_eq!(compressed_len, dlt.len() as u64);
+        assert!(compressed_len < stats.out_bytes);
+
+        let mut out = Vec::new();
+        let written = patch_gz(&mut Cursor::new(&base), &mut &dlt[..], &mut out).unwrap();
+        assert_eq!(written, new.len() as u64);
+        assert_eq!(out, new);
+    }
+
+    #[test]
+    fn unchanged() {
+        let mut sig = Vec::new();
+        signature_with_options(&mut DATA.as_bytes(), &mut sig, 10, 8, SignatureType::Blake2)
+            .unwrap();
+        assert!(is_unchanged(&mut &sig[..], &mut DATA.as_bytes()).unwrap());
+        assert!(!is_unchanged(&mut &sig[..], &mut DATA2.as_bytes()).unwrap());
+        // a prefix of the basis file
+        assert!(!is_unchanged(&mut &sig[..], &mut &DATA.as_bytes()[..20]).unwrap());
+        // the basis file repeated
+        let repeated = DATA.repeat(2);
+        assert!(!is_unchanged(&mut &sig[..], &mut repeated.as_bytes()).unwrap());
+
+        let mut sig = Vec::new();
+        signature(&mut &b""[..], &mut sig).unwrap();
+        assert!(is_unchanged(&mut &sig[..], &mut &b""[..]).unwrap());
+        assert!(!is_unchanged(&mut &sig[..], &mut DATA.as_bytes()).unwrap());
+    }
+
+    #[test]
+    fn to_vec() {
+        let sig = signature_to_vec(
+            &mut Cursor::new(DATA),
+            DATA.len() as u64,
+            10,
+            5,
+            SignatureType::Blake2,
+        )
+        .unwrap();
+        assert_eq!(sig.capacity(), sig.len());
+
+        let dlt = delta_to_vec(&mut Cursor::new(DATA2), &mut Cursor::new(sig), 64).unwrap();
+        assert!(dlt.capacity() >= 64);
+        let out = patch_to_vec(&mut Cursor::new(DATA), &mut Cursor::new(dlt), DATA2.len()).unwrap();
+        assert_eq!(out.capacity(), DATA2.len());
+        assert_eq!(from_utf8(&out).unwrap(), DATA2);
+    }
+
+    fn hex(digest: &Digest) -> String {
+        digest
+            .as_bytes()
+            .iter()
+            .map(|b| format!("{:02x}", b))
+            .collect()
+    }
+
+    #[test]
+    fn file_hash_vectors() {
+        let md4 = file_hash(&mut Cursor::new(""), DigestKind::MD4).unwrap();
+        assert_eq!(hex(&md4), "31d6cfe0d16ae931b73c59d7e0c089c0");
+        let md4 = file_hash(&mut Cursor::new("abc"), DigestKind::MD4).unwrap();
+        assert_eq!(md4.kind(), DigestKind::MD4);
+        assert_eq!(hex(&md4), "a448017aaf21d8525fc10ae87aa6729d");
+
+        let blake2 = file_hash(&mut Cursor::new("abc"), DigestKind::Blake2).unwrap();
+        assert_eq!(blake2.kind(), DigestKind::Blake2);
+        assert_eq!(
+            hex(&blake2),
+            "bddd813c634239723171ef3fee98579b94964e3bb1cb3e427262c8c068d52319"
+        );
+        let blake2 = file_hash(&mut Cursor::new(DATA), DigestKind::Blake2).unwrap();
+        assert_eq!(
+            hex(&blake2),
+            "070944f30877a6c8940467fa9de65456a0e9e1a2984348f97db15644310daf31"
+        );
+    }
+
+    #[test]
+    fn patch_verification() {
+        let mut dlt = Vec::new();
+        delta_from_files(
+            &mut Cursor::new(DATA),
+            &mut Cursor::new(DATA2),
+            &mut dlt,
+            10,
+            5,
+            SignatureType::Blake2,
+        )
+        .unwrap();
+        for &kind in &[DigestKind::MD4, DigestKind::Blake2] {
+            let expected = file_hash(&mut Cursor::new(DATA2), kind).unwrap();
+
+            let mut out = Vec::new();
+            let stats = patch_verified(
+                &mut Cursor::new(DATA),
+                &mut Cursor::new(&dlt),
+                &mut out,
+                &expected,
+            )
+            .unwrap();
+            assert_eq!(from_utf8(&out).unwrap(), DATA2);
+            assert_eq!(stats.out_bytes, out.len() as u64);
+
+            // tamper with the literal data
+            let mut tampered = dlt.clone();
+            tampered[6] ^= 0xff;
+            let res = patch_verified(
+                &mut Cursor::new(DATA),
+                &mut Cursor::new(tampered),
+                &mut Vec::new(),
+                &expected,
+            );
+            match res {
+                Err(Error::Corrupt) => (),
+                _ => panic!("expected corrupt error"),
+            }
+        }
+    }
+
+    #[test]
+    fn delta_from_files_patches() {
+        let mut dlt = Vec::new();
+        let stats = delta_from_files(
+            &mut Cursor::new(DATA),
+            &mut Cursor::new(DATA2),
+            &mut dlt,
+            10,
+            5,
+            SignatureType::Blake2,
+        )
+        .unwrap();
+        assert_eq!(stats.out_bytes, dlt.len() as u64);
+
+        let mut out = Vec::new();
+        patch(&mut Cursor::new(DATA), &mut Cursor::new(dlt), &mut out).unwrap();
+        assert_eq!(from_utf8(&out).unwrap(), DATA2);
+    }
+
+    #[test]
+    fn bidirectional() {
+        let old = (0..10_000).map(|i| (i % 251) as u8).collect::<Vec<_>>();
+        let mut new = old.clone();
+        new[5000..5100].copy_from_slice(&[0; 100]);
+        new.extend_from_slice(DATA.as_bytes());
+        let (forward, reverse) =
+            bidirectional_delta(&old, &new, 64, 0, SignatureType::Blake2).unwrap();
+
+        let mut out = Vec::new();
+        patch(&mut Cursor::new(&old), &mut &forward[..], &mut out).unwrap();
+        assert_eq!(out, new);
+        let mut out = Vec::new();
+        patch(&mut Cursor::new(&new), &mut &reverse[..], &mut out).unwrap();
+        assert_eq!(out, old);
+    }
+
+    #[test]
+    fn roundtrip_inputs() {
+        let data = (0..10_000).map(|i| (i % 251) as u8).collect::<Vec<_>>();
+        let mut changed = data.clone();
+        changed[5000..5100].copy_from_slice(&[0; 100]);
+        let inputs: &[(&[u8], &[u8])] = &[
+            (DATA.as_bytes(), DATA2.as_bytes()),
+            (DATA2.as_bytes(), DATA.as_bytes()),
+            (b"", DATA.as_bytes()),
+            (DATA.as_bytes(), b""),
+            (&data, &changed),
+            (&data, &data),
+        ];
+        for &(base, new) in inputs {
+            for &sig_type in &[SignatureType::MD4, SignatureType::Blake2] {
+                let out = roundtrip(base, new, 16, 0, sig_type).unwrap();
+                assert_eq!(out, new);
+            }
+        }
+
+        let out = roundtrip(&data, &changed, 0, 0, SignatureType::Blake2).unwrap();
+        assert_eq!(out, changed);
+        assert!(roundtrip(&data, &changed, 16, 33, SignatureType::Blake2).is_err());
+        assert!(roundtrip(&data, &changed, 16, 17, SignatureType::MD4).is_err());
+    }
+
+    // A writer accepting a few bytes at a time, and interrupted every other write.
+    struct ShortWriter {
+        data: Vec<u8>,
+        writes: usize,
+        max_len: usize,
+    }
+
+    impl Write for ShortWriter {
+        fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+            self.writes += 1;
+            if self.writes % 2 == 0 {
+                return Err(io::Error::from(io::ErrorKind::Interrupted));
+            }
+            let len = cmp::min(buf.len(), self.max_len);
+            self.data.extend_from_slice(&buf[..len]);
+            Ok(len)
+        }
+
+        fn flush(&mut self) -> io::Result<()> {
+            Ok(())
+        }
+    }
+
+    #[test]
+    fn forward_signature() {
+        let data: Vec<u8> = (0..10_000u32).map(|i| (i * 7 % 251) as u8).collect();
+        let mut sig = ShortWriter {
+            data: Vec::new(),
+            writes: 0,
+            max_len: 7,
+        };
+        let mut passthrough = ShortWriter {
+            data: Vec::new(),
+            writes: 0,
+            max_len: 100,
+        };
+        let info = sign_and_forward(&data[..], &mut sig, &mut passthrough).unwrap();
+        assert_eq!(passthrough.data, data);
+        assert_eq!(info, SignatureInfo::parse(&sig.data).unwrap());
+        let mut expected = Vec::new();
+        signature(&mut &data[..], &mut expected).unwrap();
+        assert_eq!(sig.data, expected);
+
+        let mut changed = data.clone();
+        changed.splice(5000..5000, DATA2.bytes());
+        let mut dlt = Vec::new();
+        delta(&mut &changed[..], &mut &sig.data[..], &mut dlt).unwrap();
+        let mut out = Vec::new();
+        patch(&mut Cursor::new(&data), &mut &dlt[..], &mut out).unwrap();
+        assert_eq!(out, changed);
+
+        let mut full = [0; 16];
+        let res = sign_and_forward(&data[..], &mut Vec::new(), &mut &mut full[..]);
+        match res {
+            Err(Error::Io(ref e)) if e.kind() == io::ErrorKind::WriteZero => (),
+            _ => panic!("expected a write error"),
+        }
+    }
+
+    #[test]
+    fn delta_append() {
+        let mut sig = Vec::new();
+        signature_with_options(&mut DATA.as_bytes(), &mut sig, 10, 5, SignatureType::MD4).unwrap();
+        let mut dlt = Vec::new();
+        delta(&mut DATA2.as_bytes(), &mut &sig[..], &mut dlt).unwrap();
+
+        let dir = std::env::temp_dir().join(format!("librsync-append-{}", process::id()));
+        fs::create_dir_all(&dir).unwrap();
+        let archive = dir.join("archive");
+        fs::write(&archive, b"header").unwrap();
+        for _ in 0..2 {
+            let mut file = OpenOptions::new().append(true).open(&archive).unwrap();
+            let written = delta(&mut DATA2.as_bytes(), &mut &sig[..], &mut file).unwrap();
+            assert_eq!(written, dlt.len() as u64);
+        }
+        let mut expected = b"header".to_vec();
+        expected.extend_from_slice(&dlt);
+        expected.extend_from_slice(&dlt);
+        assert_eq!(fs::read(&archive).unwrap(), expected);
+        fs::remove_dir_all(&dir).unwrap();
+
+        let mut short = ShortWriter {
+            data: Vec::new(),
+            writes: 0,
+            max_len: 3,
+        };
+        delta(&mut DATA2.as_bytes(), &mut &sig[..], &mut short).unwrap();
+        assert_eq!(short.data, dlt);
+
+        let mut full = ShortWriter {
+            data: Vec::new(),
+            writes: 0,
+            max_len: 0,
+        };
+        match delta(&mut DATA2.as_bytes(), &mut &sig[..], &mut full) {
+            Err(Error::Io(ref e)) if e.kind() == io::ErrorKind::WriteZero => (),
+            _ => panic!("expected a write zero error"),
+        }
+    }
+
+    #[test]
+    fn untrusted_deltas() {
+        let mut sig = Vec::new();
+        signature_with_options(&mut DATA.as_bytes(), &mut sig, 10, 5, SignatureType::MD4).unwrap();
+        let mut dlt = Vec::new();
+        delta(&mut DATA2.as_bytes(), &mut &sig[..], &mut dlt).unwrap();
+        let out = apply_untrusted(DATA.as_bytes(), &dlt).unwrap();
+        assert_eq!(from_utf8(&out).unwrap(), DATA2);
+
+        let mut bad_magic = dlt.clone();
+        bad_magic[3] = 0x99;
+        // copies of the whole base, expanding beyond the output limit
+        let mut huge = vec![0x72, 0x73, 0x02, 0x36];
+        for _ in 0..100 {
+            huge.extend_from_slice(&[0x46, 0x00, 0x10, 0x00]);
+        }
+        huge.push(0x00);
+        let malformed: &[&[u8]] = &[
+            b"",
+            b"rs",
+            &bad_magic,
+            // truncated
+            &dlt[..dlt.len() - 1],
+            &dlt[..8],
+            // copy beyond the end of the base
+            &[0x72, 0x73, 0x02, 0x36, 0x45, 0x14, 0x14, 0x00],
+            // copy at a negative offset
+            &[
+                0x72, 0x73, 0x02, 0x36, 0x51, 0xff, 0xff, 0xff, 0xff, 0xff, 0xff, 0xff, 0xff, 0x05,
+                0x00,
+            ],
+        ];
+        for &delta in malformed {
+            assert!(apply_untrusted(DATA.as_bytes(), delta).is_err());
+        }
+        let base = vec![0; 4096];
+        assert_eq!(apply_limited(&base, &huge, 409_600).unwrap().len(), 409_600);
+        assert!(apply_limited(&base, &huge, 100_000).is_err());
+    }
+
+    #[test]
+    fn min_match() {
+        let base = (0..=255).collect::<Vec<u8>>();
+        // 4 bytes blocks of the base, not contiguous, separated by unmatched data
+        let mut new = Vec::new();
+        for i in 0..30 {
+            new.extend_from_slice(&base[i * 8..i * 8 + 4]);
+            new.extend_from_slice(&[0xff, 0xfe, 0xfd]);
+        }
+        let mut sig = Vec::new();
+        signature_with_options(&mut &base[..], &mut sig, 4, 8, SignatureType::Blake2).unwrap();
+        let copies = |dlt: &[u8]| {
+            DeltaCommands::new(dlt)
+                .filter(|cmd| matches!(cmd, Ok(Command::Copy { .. })))
+                .count()
+        };
+
+        let mut dlt = Vec::new();
+        let written = delta_min_match(&mut &new[..], &mut &sig[..], &mut dlt, 4).unwrap();
+        assert_eq!(written, dlt.len() as u64);
+        assert_eq!(copies(&dlt), 30);
+        let mut out = Vec::new();
+        patch(&mut Cursor::new(&base), &mut &dlt[..], &mut out).unwrap();
+        assert_eq!(out, new);
+
+        let mut dlt = Vec::new();
+        delta_min_match(&mut &new[..], &mut &sig[..], &mut dlt, 5).unwrap();
+        assert_eq!(copies(&dlt), 0);
+        assert_eq!(DeltaCommands::new(&dlt[..]).count(), 1);
+        let mut out = Vec::new();
+        patch(&mut Cursor::new(&base), &mut &dlt[..], &mut out).unwrap();
+        assert_eq!(out, new);
+    }
+
+    #[test]
+    fn sparse() {
+        let mut base = vec![1; 1000];
+        base.extend_from_slice(&[0; 1 << 20]);
+        base.extend_from_slice(&[2; 1000]);
+        let mut new = base.clone();
+        new[500..600].copy_from_slice(&[3; 100]);
+        new.extend_from_slice(&[0; 10_000]);
+        let mut dlt = Vec::new();
+        delta_from_files(
+            &mut &base[..],
+            &mut &new[..],
+            &mut dlt,
+            1024,
+            8,
+            SignatureType::Blake2,
+        )
+        .unwrap();
+
+        let dir = std::env::temp_dir().join(format!("librsync-sparse-{}", process::id()));
+        fs::create_dir_all(&dir).unwrap();
+        let sparse = dir.join("sparse");
+        let file = File::create(&sparse).unwrap();
+        let stats = patch_sparse(&mut Cursor::new(&base), &mut &dlt[..], &file).unwrap();
+        assert_eq!(stats.out_bytes, new.len() as u64);
+        file.sync_all().unwrap();
+        assert_eq!(fs::read(&sparse).unwrap(), new);
+
+        #[cfg(unix)]
+        {
+            use std::os::unix::fs::MetadataExt;
+            // the file is mostly zeros, so most of it must be left as holes, in 512 bytes blocks
+            let allocated = fs::metadata(&sparse).unwrap().blocks() * 512;
+            assert!(allocated < new.len() as u64 / 2);
+        }
+        fs::remove_dir_all(&dir).unwrap();
+    }
 }
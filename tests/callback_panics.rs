@@ -0,0 +1,57 @@
+//! Panics raised inside callbacks invoked by librsync must not unwind into C code.
+//!
+//! These tests live in their own binary, because they install a process-wide logger.
+
+#![cfg(feature = "log")]
+
+use librsync::Patch;
+use log::{LevelFilter, Log, Metadata, Record};
+use std::io::{self, Cursor, Read, Seek, SeekFrom};
+
+struct PanickingLogger;
+
+impl Log for PanickingLogger {
+    fn enabled(&self, _metadata: &Metadata) -> bool {
+        true
+    }
+
+    fn log(&self, _record: &Record) {
+        panic!("logger failure");
+    }
+
+    fn flush(&self) {}
+}
+
+static LOGGER: PanickingLogger = PanickingLogger;
+
+struct PanickingBase;
+
+impl Read for PanickingBase {
+    fn read(&mut self, _buf: &mut [u8]) -> io::Result<usize> {
+        panic!("base read failure");
+    }
+}
+
+impl Seek for PanickingBase {
+    fn seek(&mut self, _pos: SeekFrom) -> io::Result<u64> {
+        Ok(0)
+    }
+}
+
+#[test]
+fn panicking_callbacks() {
+    log::set_logger(&LOGGER).unwrap();
+    log::set_max_level(LevelFilter::Trace);
+
+    // a bad magic number makes librsync log an error
+    let delta = Cursor::new(vec![0x00, 0x01, 0x02, 0x03, 0x00]);
+    let mut patch = Patch::new(Cursor::new("base"), delta).unwrap();
+    let mut out = Vec::new();
+    assert!(patch.read_to_end(&mut out).is_err());
+
+    // a copy command reads from the base
+    let delta = Cursor::new(vec![0x72, 0x73, 0x02, 0x36, 0x45, 0x00, 0x02, 0x00]);
+    let mut patch = Patch::new(PanickingBase, delta).unwrap();
+    let mut out = Vec::new();
+    assert!(patch.read_to_end(&mut out).is_err());
+}
@@ -0,0 +1,91 @@
+//! librsync messages are forwarded to `tracing` events, inside a span for each job.
+//!
+//! This test lives in its own binary, because it installs a process-wide subscriber.
+
+#![cfg(feature = "tracing")]
+
+use librsync::{Delta, Signature, SignatureType};
+use std::fmt::Write;
+use std::io::Read;
+use std::sync::{Arc, Mutex};
+use tracing::field::{Field, Visit};
+use tracing::span::{Attributes, Id};
+use tracing::{Event, Level, Subscriber};
+use tracing_subscriber::layer::{Context, SubscriberExt};
+use tracing_subscriber::registry::LookupSpan;
+use tracing_subscriber::Layer;
+
+// Records the events, along with the fields of their enclosing span, and the created spans.
+#[derive(Clone, Default)]
+struct Capture {
+    events: Arc<Mutex<Vec<(Level, String, String)>>>,
+    spans: Arc<Mutex<Vec<String>>>,
+}
+
+#[derive(Default)]
+struct Fields(String);
+
+impl Visit for Fields {
+    fn record_debug(&mut self, field: &Field, value: &dyn std::fmt::Debug) {
+        let _ = write!(self.0, "{}={:?} ", field.name(), value);
+    }
+}
+
+impl<S: Subscriber + for<'a> LookupSpan<'a>> Layer<S> for Capture {
+    fn on_new_span(&self, attrs: &Attributes, id: &Id, ctx: Context<S>) {
+        let mut fields = Fields::default();
+        attrs.record(&mut fields);
+        self.spans.lock().unwrap().push(fields.0.clone());
+        ctx.span(id).unwrap().extensions_mut().insert(fields);
+    }
+
+    fn on_event(&self, event: &Event, ctx: Context<S>) {
+        let mut message = Fields::default();
+        event.record(&mut message);
+        let span = ctx
+            .event_span(event)
+            .and_then(|span| span.extensions().get::<Fields>().map(|f| f.0.clone()))
+            .unwrap_or_default();
+        self.events
+            .lock()
+            .unwrap()
+            .push((*event.metadata().level(), message.0, span));
+    }
+}
+
+#[test]
+fn delta_emits_events() {
+    let capture = Capture::default();
+    let subscriber = tracing_subscriber::registry().with(capture.clone());
+    tracing::subscriber::set_global_default(subscriber).unwrap();
+
+    // a signature with a bogus block length
+    let sig = [
+        0x72, 0x73, 0x01, 0x36, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x05,
+    ];
+    assert!(Delta::new(&b"data"[..], &mut &sig[..]).is_err());
+
+    let events = capture.events.lock().unwrap();
+    assert!(!events.is_empty());
+    assert!(events
+        .iter()
+        .any(|(level, _, span)| *level == Level::ERROR && span.contains("op=loadsig")));
+    drop(events);
+
+    // a single span for a job, even if it takes many iterations
+    let data: Vec<u8> = (0..100_000u32).map(|i| (i % 251) as u8).collect();
+    let mut sig = Vec::new();
+    Signature::with_options(&data[..], 64, 8, SignatureType::Blake2)
+        .unwrap()
+        .read_to_end(&mut sig)
+        .unwrap();
+    let mut delta = Vec::new();
+    Delta::new(&data[..], &mut &sig[..])
+        .unwrap()
+        .read_to_end(&mut delta)
+        .unwrap();
+    let spans = capture.spans.lock().unwrap();
+    let count = |op: &str| spans.iter().filter(|fields| fields.contains(op)).count();
+    assert_eq!(count("op=signature"), 1);
+    assert_eq!(count("op=delta"), 1);
+}